@@ -1,20 +1,51 @@
 use cosmwasm_std::{
-    entry_point, to_binary,   CosmosMsg, Deps, DepsMut,Binary,
-    Env, MessageInfo,  Response, StdResult, Uint128, WasmMsg,  Order
+    coins, entry_point, from_binary, to_binary, BankMsg, Binary, CosmosMsg, Deps, DepsMut, Env,
+    MessageInfo, Order, Response, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
 
-use cw2::set_contract_version;
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use crate::msg::{
+    ClaimSimulationResponse, CollectionStatsResponse, ExecuteMsg, InstantiateMsg, LockTierResponse,
+    MigrateMsg, PauseStateResponse, PauseSwitch, QueryMsg, ReceiveMsg, StakeMsg, StatsResponse,
+    StreamRewardResponse, TokenRewardsResponse, TraitWeightResponse,
+};
 use crate::state::{
-    State,CONFIG,TOKENINFO,OWNEDTOKEN, TokenInfo
+    tokens, Activity, ActivityKind, CollectionInfo, Distribution, Metadata, PauseState,
+    PendingOwner, QueuedAction, RewardAsset, RewardStream, Role, State, Stats, StreamReward,
+    TimelockedAction, TokenInfo, TokenKey, COLLECTIONS, CONFIG, DISTRIBUTIONS, DISTRIBUTION_COUNT,
+    LEGACY_CONFIG, LEGACY_OWNEDTOKEN, LEGACY_TOKENINFO, LOCK_ENDS, LOCK_TIERS, PAUSE_STATE,
+    PENDING_OWNER, QUEUED_ACTIONS, QUEUED_ACTION_COUNT, REWARD_STREAMS, ROLES, STATS, STREAM_COUNT,
+    STREAM_REWARDS, TOKEN_WEIGHTS, TRAIT_WEIGHTS, USER_HISTORY, USER_HISTORY_COUNT,
 };
-use cw721::{Cw721ExecuteMsg, Cw721ReceiveMsg,Cw721QueryMsg, AllNftInfoResponse};
-use cw20::{Cw20ExecuteMsg};
-
+use cw2::{get_contract_version, set_contract_version};
+use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw721::{AllNftInfoResponse, Cw721ExecuteMsg, Cw721QueryMsg, Cw721ReceiveMsg};
+use cw_storage_plus::{Bound, PrimaryKey};
+use semver::Version;
+use std::convert::TryFrom;
 
 const CONTRACT_NAME: &str = "NFT_STAKING";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Weight of a token without an owner-set weight.
+const DEFAULT_WEIGHT: u64 = 100;
+/// Upper bound of every owner-set weight and multiplier, 100x the default.
+const MAX_WEIGHT: u64 = 10_000;
+/// Most reward streams next to the primary one. Streams are never removed, and every token
+/// operation settles all of them.
+const MAX_REWARD_STREAMS: u64 = 5;
+/// Longest `State.timelock_delay`, so a queued change cannot be put out of reach for good.
+const MAX_TIMELOCK_DELAY: u64 = 30 * 24 * 60 * 60;
+/// Fixed-point scale of `reward_per_token` and `reward_remainder`. With 6-decimal reward tokens
+/// single amounts can reach about 3.4e20 whole tokens; with 18 decimals about 3.4e8, and larger
+/// ones are refused with an overflow error rather than accepted.
+const REWARD_SCALE: u128 = 1_000_000_000_000;
+
+/// Latest distributions the reward estimate of `Stats` is based on.
+const RECENT_DISTRIBUTIONS: usize = 10;
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
 
 #[entry_point]
 pub fn instantiate(
@@ -23,21 +54,41 @@ pub fn instantiate(
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
-     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    for (index, address) in msg.nft_address.iter().enumerate() {
+        if msg.nft_address[..index].contains(address) {
+            return Err(ContractError::DuplicateNftContract {});
+        }
+    }
+
     let state = State {
-        owner:info.sender.to_string(),
-        denom:msg.denom,
-        staking_period : msg.staking_period,
-        distribute_period:msg.distribute_period,
-        reward_wallet : msg.reward_wallet,
-        total_staked : Uint128::new(0),
-        nft_address : msg.nft_address,
-        token_address : msg.token_address,
+        owner: info.sender.to_string(),
+        denom: msg.denom,
+        staking_period: msg.staking_period,
+        distribute_period: msg.distribute_period,
+        reward_wallet: msg.reward_wallet,
+        total_staked: Uint128::new(0),
+        nft_address: msg.nft_address,
+        token_address: msg.token_address,
         can_stake: true,
-        last_distribute:env.block.time.seconds(),
-        claim_reward:msg.claim_reward
+        last_distribute: env.block.time.seconds(),
+        claim_reward: msg.claim_reward,
+        reward_per_token: Uint128::new(0),
+        emission_rate: Uint128::new(0),
+        emission_start: 0,
+        emission_end: 0,
+        emission_balance: Uint128::new(0),
+        last_emission: env.block.time.seconds(),
+        reward_remainder: Uint128::new(0),
+        total_weight: Uint128::new(0),
+        emergency_penalty: 0,
+        treasury: None,
+        timelock_delay: 0,
     };
-    CONFIG.save(deps.storage,&state)?;
+    for address in &state.nft_address {
+        register_collection(deps.storage, address)?;
+    }
+    CONFIG.save(deps.storage, &state)?;
     Ok(Response::default())
 }
 
@@ -50,907 +101,5227 @@ pub fn execute(
 ) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::ReceiveNft(rcv_msg) => execute_stake_nft(deps, env, info, rcv_msg),
-        ExecuteMsg::UnstakeNft { token_id } => execute_unstake_nft(deps, env, info, token_id),
-        ExecuteMsg::WithdrawNft { token_id } => execute_withdraw_nft(deps, env, info, token_id),
-        ExecuteMsg::GetReward {token_ids} =>execute_get_reward(deps, env, info, token_ids),
-        ExecuteMsg::DistributeReward { token_amount } => execute_distribute_reward(deps,env,info,token_amount),
-        ExecuteMsg::SetRewardWallet { address } => execute_reward_wallet(deps,env,info,address),
-        ExecuteMsg::SetTokenAddress { address } => execute_token_address(deps,env,info,address),
-        ExecuteMsg::SetOwner { address } => execute_set_owner(deps, env, info, address),
-        ExecuteMsg::SetStakingPeriod { time } => execute_staking_period(deps,env,info,time),
-        ExecuteMsg::SetStake { flag } => execute_set_stake(deps,info,flag),
-        ExecuteMsg::SetDistributePeriod { time } => execute_distribute_period(deps, env, info, time),
-        ExecuteMsg::Migrate {amount,address,id} => execute_migrate_token(deps, env, info, amount,address,id),
-        ExecuteMsg::SetClaimAmount { amount }=> execute_claim_amount(deps, env, info, amount),
-        ExecuteMsg::AddNftAddress { address } => execute_nft_address(deps,env,info,address)
+        ExecuteMsg::UnstakeNft {
+            nft_address,
+            token_id,
+        } => execute_unstake_nfts(
+            deps,
+            env,
+            info,
+            vec![TokenKey {
+                nft_address,
+                token_id,
+            }],
+        ),
+        ExecuteMsg::WithdrawNft {
+            nft_address,
+            token_id,
+        } => execute_withdraw_nfts(
+            deps,
+            env,
+            info,
+            vec![TokenKey {
+                nft_address,
+                token_id,
+            }],
+        ),
+        ExecuteMsg::UnstakeNfts { token_ids } => execute_unstake_nfts(deps, env, info, token_ids),
+        ExecuteMsg::WithdrawNfts { token_ids } => execute_withdraw_nfts(deps, env, info, token_ids),
+        ExecuteMsg::EmergencyWithdraw {
+            nft_address,
+            token_id,
+        } => execute_emergency_withdraw(deps, env, info, nft_address, token_id),
+        ExecuteMsg::CancelUnstake {
+            nft_address,
+            token_id,
+        } => execute_cancel_unstake(deps, env, info, nft_address, token_id),
+        ExecuteMsg::GetReward { token_ids } => execute_get_reward(deps, env, info, token_ids),
+        ExecuteMsg::Receive(rcv_msg) => execute_receive(deps, env, info, rcv_msg),
+        ExecuteMsg::SetRewardWallet { address } => execute_queue_action(
+            deps,
+            env,
+            info,
+            TimelockedAction::SetRewardWallet { address },
+        ),
+        ExecuteMsg::SetTokenAddress { address } => execute_queue_action(
+            deps,
+            env,
+            info,
+            TimelockedAction::SetTokenAddress { address },
+        ),
+        ExecuteMsg::SetTimelockDelay { delay } => execute_queue_action(
+            deps,
+            env,
+            info,
+            TimelockedAction::SetTimelockDelay { delay },
+        ),
+        ExecuteMsg::ExecuteQueuedAction { id } => execute_queued_action(deps, env, info, id),
+        ExecuteMsg::CancelQueuedAction { id } => execute_cancel_queued_action(deps, info, id),
+        ExecuteMsg::ProposeOwner {
+            address,
+            expires_at,
+        } => execute_propose_owner(deps, env, info, address, expires_at),
+        ExecuteMsg::AcceptOwnership {} => execute_accept_ownership(deps, env, info),
+        ExecuteMsg::GrantRole { role, address } => execute_grant_role(deps, info, role, address),
+        ExecuteMsg::RevokeRole { role, address } => execute_revoke_role(deps, info, role, address),
+        ExecuteMsg::CancelOwnershipTransfer {} => execute_cancel_ownership_transfer(deps, info),
+        ExecuteMsg::SetStakingPeriod { time } => {
+            execute_queue_action(deps, env, info, TimelockedAction::SetStakingPeriod { time })
+        }
+        ExecuteMsg::SetStake { flag } => {
+            execute_set_paused(deps, info, PauseSwitch::Staking, !flag)
+        }
+        ExecuteMsg::SetPaused { switch, paused } => execute_set_paused(deps, info, switch, paused),
+        ExecuteMsg::SetDistributePeriod { time } => {
+            execute_distribute_period(deps, env, info, time)
+        }
+        ExecuteMsg::Migrate {
+            amount,
+            address,
+            id,
+        } => execute_migrate_token(deps, env, info, amount, address, id),
+        ExecuteMsg::SetClaimAmount { amount } => execute_claim_amount(deps, env, info, amount),
+        ExecuteMsg::AddNftAddress { address } => execute_nft_address(deps, env, info, address),
+        ExecuteMsg::RemoveNftAddress { address } => execute_remove_nft_address(deps, info, address),
+        ExecuteMsg::DisableNftAddress { address } => {
+            execute_disable_nft_address(deps, info, address)
+        }
+        ExecuteMsg::SetEmergencyPenalty { percent, treasury } => execute_queue_action(
+            deps,
+            env,
+            info,
+            TimelockedAction::SetEmergencyPenalty { percent, treasury },
+        ),
+        ExecuteMsg::SetLockTier {
+            duration,
+            multiplier,
+        } => execute_lock_tier(deps, info, duration, multiplier),
+        ExecuteMsg::SetRewardEmission {
+            rate,
+            start_time,
+            end_time,
+        } => execute_reward_emission(deps, env, info, rate, start_time, end_time),
+        ExecuteMsg::AddRewardStream { asset, funder } => {
+            execute_add_reward_stream(deps, env, info, asset, funder)
+        }
+        ExecuteMsg::DistributeNativeReward { stream_id } => {
+            execute_distribute_native_reward(deps, env, info, stream_id)
+        }
+        ExecuteMsg::SetTraitWeight {
+            nft_address,
+            trait_type,
+            value,
+            weight,
+        } => execute_trait_weight(deps, info, nft_address, trait_type, value, weight),
+        ExecuteMsg::SetTokenWeight {
+            nft_address,
+            token_id,
+            weight,
+        } => execute_token_weight(deps, info, nft_address, token_id, weight),
+        ExecuteMsg::SetCollectionConfig {
+            address,
+            reward_weight,
+            enabled,
+            max_staked,
+        } => execute_collection_config(deps, info, address, reward_weight, enabled, max_staked),
+        ExecuteMsg::SetUnbondingPeriod {
+            address,
+            unbonding_period,
+        } => execute_queue_action(
+            deps,
+            env,
+            info,
+            TimelockedAction::SetUnbondingPeriod {
+                address,
+                unbonding_period,
+            },
+        ),
     }
 }
 
-fn execute_stake_nft(
-    deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-    rcv_msg: Cw721ReceiveMsg,
-) -> Result<Response, ContractError> {
-    let state = CONFIG.load(deps.storage)?;
+/// Credits everything a token of `weight` earned since `checkpoint` to `reward` and moves the
+/// checkpoint up to `reward_per_token`. Only staked tokens take part in distributions, so
+/// unstaking tokens are left untouched.
+///
+/// Both ends are scaled down before subtracting, so the fractions cut off by rounding do not add
+/// up across settlements.
+fn accrue(
+    staked: bool,
+    weight: u64,
+    reward_per_token: Uint128,
+    reward: &mut Uint128,
+    checkpoint: &mut Uint128,
+) {
+    if staked {
+        *reward += reward_per_token.multiply_ratio(weight, REWARD_SCALE)
+            - checkpoint.multiply_ratio(weight, REWARD_SCALE);
+    }
+    *checkpoint = reward_per_token;
+}
+
+/// A lock that ran out, with the primary `reward_per_token` at its `lock_end`.
+struct LockExpiry {
+    token_info: TokenInfo,
+    reward_per_token: Uint128,
+}
 
-    let token = TOKENINFO.may_load(deps.storage, &rcv_msg.token_id.clone())?;
+/// Brings the emission up to `now`, taking every lock that ran out on the way out of
+/// `total_weight` at its `lock_end`. Returns those locks in the order they ended, with their
+/// tokens still at the locked weight.
+fn advance_state(storage: &dyn Storage, state: &mut State, now: u64) -> StdResult<Vec<LockExpiry>> {
+    let mut expiries: Vec<LockExpiry> = vec![];
+    for key in LOCK_ENDS.keys(storage, None, None, Order::Ascending) {
+        let (lock_end, nft_address, token_id) = key?;
+        if lock_end > now {
+            break;
+        }
 
-    if state.can_stake == false{
-        return Err(ContractError::CanNotStake{})
+        update_emission(state, lock_end)?;
+        let token_info = tokens().load(storage, (&nft_address, &token_id))?;
+        state.total_weight = state.total_weight - Uint128::from(token_info.weight)
+            + Uint128::from(token_info.base_weight);
+        expiries.push(LockExpiry {
+            token_info,
+            reward_per_token: state.reward_per_token,
+        });
     }
-    
-    let sender = info.sender.to_string();
-    let mut is_registered = false;
+    update_emission(state, now)?;
+    Ok(expiries)
+}
 
-    for address in state.nft_address{
-        if address == sender{
-            is_registered = true
-        }
+/// `advance_state` for messages that write: every token whose lock ran out is settled at the
+/// locked weight up to its `lock_end` and stored at its `base_weight` from then on.
+fn expire_locks(storage: &mut dyn Storage, state: &mut State, now: u64) -> StdResult<()> {
+    let expiries = advance_state(storage, state, now)?;
+    if expiries.is_empty() {
+        return Ok(());
+    }
+
+    // streams are only bumped by distributions, which expire due locks first, so their current
+    // accumulators are where the locks ended
+    let streams = load_reward_streams(storage)?;
+    for expiry in expiries {
+        let mut token_info = expiry.token_info;
+        let stream_rewards = settle_stream_rewards(storage, &streams, &token_info)?;
+        save_stream_rewards(storage, &streams, &token_info, &stream_rewards)?;
+        expire_lock(&mut token_info, expiry.reward_per_token);
+
+        let nft_address = token_info.nft_address.as_str();
+        let token_id = token_info.token_id.as_str();
+        LOCK_ENDS.remove(storage, (token_info.lock_end, nft_address, token_id));
+        tokens().save(storage, (nft_address, token_id), &token_info)?;
     }
+    Ok(())
+}
 
-    if !is_registered{
-        return Err(ContractError::WrongNftContract {  });
-    } 
+/// Settles a token at the locked weight up to `reward_per_token`, where its lock ended, and
+/// drops it to its `base_weight`.
+fn expire_lock(token_info: &mut TokenInfo, reward_per_token: Uint128) {
+    accrue(
+        true,
+        token_info.weight,
+        reward_per_token,
+        &mut token_info.reward,
+        &mut token_info.reward_checkpoint,
+    );
+    token_info.weight = token_info.base_weight;
+}
 
-    if token != None {
-        return Err(ContractError::AlreadyStaked {  });
+/// Applies to `token_info` an expiry `advance_state` found but nothing stored yet, so queries
+/// see the token as the next write will leave it.
+fn apply_expiry(expiries: &[LockExpiry], token_info: &mut TokenInfo) {
+    let expiry = expiries.iter().find(|expiry| {
+        expiry.token_info.nft_address == token_info.nft_address
+            && expiry.token_info.token_id == token_info.token_id
+    });
+    if let Some(expiry) = expiry {
+        expire_lock(token_info, expiry.reward_per_token);
     }
-   
-    CONFIG.update(deps.storage,
-        |mut state|->StdResult<_>{
-            state.total_staked = state.total_staked + Uint128::new(1) ;
-            Ok(state)
-        }
-    )?;
+}
 
-    let token_info = TokenInfo{
-        owner:rcv_msg.sender.clone(),
-        token_id:rcv_msg.token_id.clone(),
-        status : "Staked".to_string(),
-        unstake_time : 0,
-        stake_time :env.block.time.seconds(),
-        reward: Uint128::new(0),
-        nft_address:sender
-    };
+fn settle_reward(state: &State, token_info: &mut TokenInfo) {
+    accrue(
+        token_info.status == "Staked",
+        token_info.weight,
+        state.reward_per_token,
+        &mut token_info.reward,
+        &mut token_info.reward_checkpoint,
+    );
+}
 
-    let my_nfts = OWNEDTOKEN.may_load(deps.storage,&rcv_msg.sender.clone().to_string())?;
+/// Splits `amount` and the carried `remainder` evenly over `total_weight` units of weight.
+/// Returns the share of each unit scaled by `REWARD_SCALE` and leaves whatever does not divide
+/// evenly in `remainder`, so the shares handed out plus the remainder always add up to
+/// everything funded.
+fn split_reward(
+    amount: Uint128,
+    remainder: &mut Uint128,
+    total_weight: Uint128,
+) -> StdResult<Uint128> {
+    let total = amount
+        .checked_mul(Uint128::new(REWARD_SCALE))?
+        .checked_add(*remainder)?;
+    let per_weight = total / total_weight;
+    *remainder = total - per_weight * total_weight;
+    Ok(per_weight)
+}
 
-    if my_nfts == None{
-        let mut token_ids:Vec<String> = vec![];
-        token_ids.push(rcv_msg.token_id.clone());
-        OWNEDTOKEN.save(deps.storage,&rcv_msg.sender,&token_ids)?;
+/// Weight of a token about to be staked: the one set for the token itself, otherwise the
+/// highest of its weighted traits, otherwise `DEFAULT_WEIGHT`.
+fn token_weight(deps: Deps, nft_address: &str, token_id: &str) -> StdResult<u64> {
+    if let Some(weight) = TOKEN_WEIGHTS.may_load(deps.storage, (nft_address, token_id))? {
+        return Ok(weight);
+    }
 
+    // collections without weighted traits are not asked for metadata they may not have
+    let has_trait_weights = TRAIT_WEIGHTS
+        .sub_prefix(nft_address)
+        .keys_raw(deps.storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if !has_trait_weights {
+        return Ok(DEFAULT_WEIGHT);
     }
 
-    else{
-        let mut token_ids = my_nfts.unwrap();
-        token_ids.push(rcv_msg.token_id.clone());
-        OWNEDTOKEN.update(deps.storage,&rcv_msg.sender,
-        |_my_nfts|->StdResult<_>{
-            Ok(token_ids)
-        }
+    let nft_info: AllNftInfoResponse<Option<Metadata>> = deps.querier.query_wasm_smart(
+        nft_address,
+        &Cw721QueryMsg::AllNftInfo {
+            token_id: token_id.to_string(),
+            include_expired: None,
+        },
     )?;
+    let attributes = nft_info
+        .info
+        .extension
+        .and_then(|metadata| metadata.attributes)
+        .unwrap_or_default();
+
+    let mut weight = None;
+    for attribute in attributes {
+        let key = (
+            nft_address,
+            attribute.trait_type.as_str(),
+            attribute.value.as_str(),
+        );
+        weight = weight.max(TRAIT_WEIGHTS.may_load(deps.storage, key)?);
     }
-
-    TOKENINFO.save(deps.storage, &rcv_msg.token_id.clone(), &token_info)?;
-    
-    Ok(Response::default())
-
+    Ok(weight.unwrap_or(DEFAULT_WEIGHT))
 }
 
+fn check_weight(weight: u64) -> Result<(), ContractError> {
+    if weight == 0 || weight > MAX_WEIGHT {
+        return Err(ContractError::InvalidWeight { max: MAX_WEIGHT });
+    }
+    Ok(())
+}
 
-
-fn execute_unstake_nft(
-    deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-    token_id: String,
-    
-) -> Result<Response, ContractError> {
-    // let state = CONFIG.load(deps.storage)?;
-
-    let token = TOKENINFO.may_load(deps.storage, &token_id)?;
-
-    if token == None {
-        return Err(ContractError::NotStaked {  });
+/// Reads the `SendNft` payload. Anything but a JSON object stakes without a lock, as before lock
+/// tiers existed, while an object that is not a valid `StakeMsg` is refused so a mistyped lock
+/// request is not staked unlocked.
+fn parse_stake_msg(msg: &Binary) -> Result<StakeMsg, ContractError> {
+    match msg.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(b'{') => from_binary(msg).map_err(|_| ContractError::InvalidLockTier {}),
+        _ => Ok(StakeMsg::default()),
     }
-   
-   else {
-      let  token = token.unwrap();    
+}
 
-      if token.owner != info.sender.to_string(){
-          return Err(ContractError::Unauthorized {  })
-      }
+/// Weight a token stakes with, from its own weight, the weight of its collection and the
+/// multiplier of its lock tier. Everything is multiplied before dividing so no factor is
+/// truncated early.
+fn staked_weight(
+    token_weight: u64,
+    reward_weight: u64,
+    multiplier: u64,
+) -> Result<u64, ContractError> {
+    let weight = Uint128::from(token_weight)
+        .checked_mul(Uint128::from(reward_weight))
+        .and_then(|weight| weight.checked_mul(Uint128::from(multiplier)))
+        .map_err(StdError::from)?
+        / Uint128::from(DEFAULT_WEIGHT * DEFAULT_WEIGHT);
+    // a token always keeps some weight, otherwise it could be the only one staked while
+    // distributions had nothing to be split over
+    u64::try_from(weight.u128().max(1))
+        .map_err(|_| ContractError::InvalidWeight { max: MAX_WEIGHT })
+}
 
-      TOKENINFO.update(deps.storage,&token_id,
-        |token_info|->StdResult<_>{
-            let mut token_info = token_info.unwrap();
-            token_info.status = "Unstaking".to_string();
-            token_info.unstake_time = env.block.time.seconds();
-            
-            Ok(token_info)
-        }
-    )?;
-   }
-     CONFIG.update(deps.storage,
-        |mut state|->StdResult<_>{
-            state.total_staked = state.total_staked-Uint128::new(1);
-            Ok(state)
-        })?;
-
-    
-    Ok(Response::default())
+/// Gives a newly allowed collection the global defaults, leaving known ones untouched.
+fn register_collection(storage: &mut dyn Storage, address: &str) -> StdResult<()> {
+    if COLLECTIONS.has(storage, address) {
+        return Ok(());
+    }
+    let collection = CollectionInfo {
+        address: address.to_string(),
+        unbonding_period: None,
+        reward_weight: DEFAULT_WEIGHT,
+        enabled: true,
+        max_staked: None,
+        total_staked: 0,
+    };
+    COLLECTIONS.save(storage, address, &collection)
+}
 
+/// Fails unless `sender` is the owner or was granted `role`.
+fn check_role(
+    storage: &dyn Storage,
+    state: &State,
+    sender: &str,
+    role: Role,
+) -> Result<(), ContractError> {
+    if sender == state.owner || ROLES.has(storage, (role.as_str(), sender)) {
+        Ok(())
+    } else {
+        Err(ContractError::Unauthorized {})
+    }
 }
 
-fn execute_withdraw_nft(
-    deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-    token_id: String,
-    
-) -> Result<Response, ContractError> {
-    let state = CONFIG.load(deps.storage)?;
-    let mut nft_address:String ;
-
-    let token = TOKENINFO.may_load(deps.storage, &token_id)?;
-
-    let mut messages:Vec<CosmosMsg> = vec![];
-    
-    if token == None {
-        return Err(ContractError::NotStaked {  });
-    }
-   
-   else {
-      let  token = token.unwrap();
-
-       if token.owner != info.sender.to_string(){
-          return Err(ContractError::Unauthorized {  })
-      }
-
-      if token.status =="Staked".to_string(){
-          return Err(ContractError::StatusError {  })
-      }
-
-      if (env.block.time.seconds() - token.unstake_time)<state.staking_period{
-           return Err(ContractError::TimeRemaining {  })
-      
-        }
-
-      nft_address = token.nft_address; 
-       
-      if token.reward > Uint128::new(0){
-      messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: state.token_address.clone(), 
-             msg: to_binary(&Cw20ExecuteMsg::Transfer {
-                 recipient: token.owner, 
-                 amount: token.reward 
-                })? , 
-        funds: vec![] }));
-    }
-    
-    TOKENINFO.remove(deps.storage,&token_id);
-    
-   }
-
-   let my_nfts = OWNEDTOKEN.load(deps.storage,&info.sender.to_string())?;
-   let mut new_nfts:Vec<String> = vec![];
-   for id  in my_nfts{
-     if id !=  token_id{
-         new_nfts.push(id)
-     }
-   }   
-
-   OWNEDTOKEN.save(deps.storage,&info.sender.to_string(),&new_nfts)?;
-  
-   Ok(Response::new()
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: nft_address, 
-             msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                  recipient: info.sender.to_string(), 
-                  token_id: token_id })? , 
-             funds: vec![] }))
-        .add_messages(messages)
-         
-)
+fn load_pause_state(storage: &dyn Storage) -> StdResult<PauseState> {
+    Ok(PAUSE_STATE.may_load(storage)?.unwrap_or_default())
 }
 
+/// Appends `kind` on `token_info` to the history of `address`.
+fn record_activity(
+    storage: &mut dyn Storage,
+    address: &str,
+    kind: ActivityKind,
+    time: u64,
+    token_info: &TokenInfo,
+    amount: Uint128,
+) -> StdResult<()> {
+    let id = USER_HISTORY_COUNT
+        .may_load(storage, address)?
+        .unwrap_or_default()
+        + 1;
+    USER_HISTORY_COUNT.save(storage, address, &id)?;
+    let activity = Activity {
+        id,
+        kind,
+        time,
+        nft_address: token_info.nft_address.clone(),
+        token_id: token_info.token_id.clone(),
+        amount,
+    };
+    USER_HISTORY.save(storage, (address, id), &activity)
+}
 
+fn load_stats(storage: &dyn Storage) -> StdResult<Stats> {
+    Ok(STATS.may_load(storage)?.unwrap_or_default())
+}
 
-fn execute_get_reward(
-    deps: DepsMut,
-    _env: Env,
-    info: MessageInfo,
-    token_ids: Vec<String>,
-    
-) -> Result<Response, ContractError> {
-    let state = CONFIG.load(deps.storage)?;
+fn load_reward_streams(storage: &dyn Storage) -> StdResult<Vec<RewardStream>> {
+    REWARD_STREAMS
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_id, stream)| stream))
+        .collect()
+}
 
-    let mut messages:Vec<CosmosMsg> = vec![];
+/// Settles `token_info` against every additional stream, returned in `streams` order.
+fn settle_stream_rewards(
+    storage: &dyn Storage,
+    streams: &[RewardStream],
+    token_info: &TokenInfo,
+) -> StdResult<Vec<StreamReward>> {
+    streams
+        .iter()
+        .map(|stream| {
+            let key = (
+                stream.id,
+                token_info.nft_address.as_str(),
+                token_info.token_id.as_str(),
+            );
+            let mut stream_reward = STREAM_REWARDS.may_load(storage, key)?.unwrap_or_default();
+            accrue(
+                token_info.status == "Staked",
+                token_info.weight,
+                stream.reward_per_token,
+                &mut stream_reward.reward,
+                &mut stream_reward.reward_checkpoint,
+            );
+            Ok(stream_reward)
+        })
+        .collect()
+}
 
-    for token_id in token_ids{
-    let token = TOKENINFO.may_load(deps.storage, &token_id)?;
-    if token == None {
-        return Err(ContractError::NotStaked {  });
+fn save_stream_rewards(
+    storage: &mut dyn Storage,
+    streams: &[RewardStream],
+    token_info: &TokenInfo,
+    stream_rewards: &[StreamReward],
+) -> StdResult<()> {
+    for (stream, stream_reward) in streams.iter().zip(stream_rewards) {
+        let key = (
+            stream.id,
+            token_info.nft_address.as_str(),
+            token_info.token_id.as_str(),
+        );
+        STREAM_REWARDS.save(storage, key, stream_reward)?;
     }
-   
-   else {
-      let  token = token.unwrap();
+    Ok(())
+}
 
-       if token.owner != info.sender.to_string(){
-          return Err(ContractError::Unauthorized {  })
-      }
+/// Drops everything stored for a token leaving the contract.
+fn remove_token(
+    storage: &mut dyn Storage,
+    streams: &[RewardStream],
+    token_info: &TokenInfo,
+) -> StdResult<()> {
+    let nft_address = token_info.nft_address.as_str();
+    let token_id = token_info.token_id.as_str();
+    tokens().remove(storage, (nft_address, token_id))?;
+    LOCK_ENDS.remove(storage, (token_info.lock_end, nft_address, token_id));
+    for stream in streams {
+        STREAM_REWARDS.remove(storage, (stream.id, nft_address, token_id));
+    }
+    Ok(())
+}
 
-       
-      if token.reward > Uint128::new(0){
-         messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: state.token_address.clone(), 
-             msg: to_binary(&Cw20ExecuteMsg::Transfer {
-                 recipient: token.owner, 
-                 amount: token.reward 
-                })? , 
-             funds: vec![] }));
-        }
+/// Logs a distribution of `amount` to `stream_id`, which raised its `reward_per_token` by
+/// `reward_per_token`.
+fn record_distribution(
+    storage: &mut dyn Storage,
+    stream_id: u64,
+    time: u64,
+    amount: Uint128,
+    total_staked: Uint128,
+    reward_per_token: Uint128,
+) -> StdResult<()> {
+    let id = DISTRIBUTION_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    DISTRIBUTION_COUNT.save(storage, &id)?;
+    let distribution = Distribution {
+        id,
+        stream_id,
+        time,
+        amount,
+        total_staked,
+        share: reward_per_token.multiply_ratio(DEFAULT_WEIGHT, REWARD_SCALE),
+    };
+    DISTRIBUTIONS.save(storage, (stream_id, id), &distribution)
+}
 
-      TOKENINFO.update(deps.storage,&token_id,
-        |token_info|->StdResult<_>{
-            let mut token_info = token_info.unwrap();
-            token_info.reward = Uint128::new(0);
-            Ok(token_info)
-        })?;
-   }
+/// Shares `amount` between the staked tokens, or keeps it for the next distribution while
+/// nothing is staked.
+fn redistribute(
+    amount: Uint128,
+    reward_per_token: &mut Uint128,
+    remainder: &mut Uint128,
+    total_weight: Uint128,
+) -> StdResult<()> {
+    if total_weight.is_zero() {
+        *remainder = amount
+            .checked_mul(Uint128::new(REWARD_SCALE))?
+            .checked_add(*remainder)?;
+    } else {
+        let per_weight = split_reward(amount, remainder, total_weight)?;
+        *reward_per_token = reward_per_token.checked_add(per_weight)?;
+    }
+    Ok(())
 }
-   
-   Ok(Response::new()
-        .add_messages(messages)
-         
-)
+
+fn nft_transfer(nft_address: &str, recipient: &str, token_id: &str) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: nft_address.to_string(),
+        msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+            recipient: recipient.to_string(),
+            token_id: token_id.to_string(),
+        })?,
+        funds: vec![],
+    }))
 }
 
+fn cw20_transfer(token_address: &str, recipient: &str, amount: Uint128) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: token_address.to_string(),
+        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+            recipient: recipient.to_string(),
+            amount,
+        })?,
+        funds: vec![],
+    }))
+}
 
+fn asset_transfer(asset: &RewardAsset, recipient: &str, amount: Uint128) -> StdResult<CosmosMsg> {
+    match asset {
+        RewardAsset::Cw20 { address } => cw20_transfer(address, recipient, amount),
+        RewardAsset::Native { denom } => Ok(CosmosMsg::Bank(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: coins(amount.u128(), denom),
+        })),
+    }
+}
 
+/// One transfer per reward token with something to pay, the primary token first.
+fn reward_messages(
+    state: &State,
+    streams: &[RewardStream],
+    recipient: &str,
+    amount: Uint128,
+    stream_amounts: &[Uint128],
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut messages: Vec<CosmosMsg> = vec![];
+    if amount > Uint128::new(0) {
+        messages.push(cw20_transfer(&state.token_address, recipient, amount)?);
+    }
+    for (stream, amount) in streams.iter().zip(stream_amounts) {
+        if *amount > Uint128::new(0) {
+            messages.push(asset_transfer(&stream.asset, recipient, *amount)?);
+        }
+    }
+    Ok(messages)
+}
 
+/// Streams the emission of every second between `state.last_emission` and `now` into
+/// `reward_per_token`. Seconds without stakers emit nothing, and the stream dries up once the
+/// funded balance is spent.
+fn update_emission(state: &mut State, now: u64) -> StdResult<()> {
+    let from = state.last_emission.max(state.emission_start);
+    let to = now.min(state.emission_end);
+
+    if to > from && !state.total_weight.is_zero() {
+        let emitted = state
+            .emission_rate
+            .saturating_mul(Uint128::from(to - from))
+            .min(state.emission_balance);
+        state.emission_balance -= emitted;
+        // whatever does not divide evenly is carried like the rest of the primary stream
+        let per_weight = split_reward(emitted, &mut state.reward_remainder, state.total_weight)?;
+        state.reward_per_token = state.reward_per_token.checked_add(per_weight)?;
+    }
 
+    state.last_emission = state.last_emission.max(now);
+    Ok(())
+}
 
-fn execute_distribute_reward(
+fn execute_stake_nft(
     deps: DepsMut,
-    env:  Env,
+    env: Env,
     info: MessageInfo,
-    token_amount:Uint128
-)->Result<Response,ContractError>{
+    rcv_msg: Cw721ReceiveMsg,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
 
-    let state = CONFIG.load(deps.storage)?;
+    let sender = info.sender.to_string();
 
-    if info.sender.to_string() != state.reward_wallet{
-        return Err(ContractError::Unauthorized {});
-    }
+    let token = tokens().may_load(deps.storage, (&sender, &rcv_msg.token_id))?;
 
-    if (env.block.time.seconds() - state.last_distribute)<state.distribute_period{
-        return Err(ContractError::CanNotDistribute {  })
+    if !state.can_stake {
+        return Err(ContractError::CanNotStake {});
     }
-    
-    let token_id :StdResult<Vec<String>>  = TOKENINFO
-        .keys(deps.storage, None, None, Order::Ascending)
-        .collect();
 
-    let token_group = token_id?;
+    let mut collection = match COLLECTIONS.may_load(deps.storage, &sender)? {
+        Some(collection) => collection,
+        None => return Err(ContractError::WrongNftContract {}),
+    };
 
-   if token_group.len() == 0 {
-       return Err(ContractError::NotStaked {  })
-   }
+    if !collection.enabled {
+        return Err(ContractError::CollectionDisabled {});
+    }
 
-    if state.total_staked == Uint128::new(0){
-        return Err(ContractError::NotStaked {  })
+    if let Some(max_staked) = collection.max_staked {
+        if collection.total_staked >= max_staked {
+            return Err(ContractError::CollectionFull {});
+        }
     }
-   
 
-    for token_id in token_group{
-            let token_info = TOKENINFO.load(deps.storage,&token_id)?;
-            if token_info.status == "Staked".to_string()
-            {       TOKENINFO.update(deps.storage, &token_id,
-                |token_info|->StdResult<_>{
-                    let mut token_info = token_info.unwrap();
-                    token_info.reward = token_info.reward + token_amount/state.total_staked;
-                    Ok(token_info)
-            }
-            )?; }
+    if token.is_some() {
+        return Err(ContractError::AlreadyStaked {});
     }
 
-    CONFIG.update(deps.storage,
-        |mut state|-> StdResult<_>{
-            state.last_distribute = env.block.time.seconds();
-            Ok(state)
-        }    
-    )?;
+    let stake_msg = parse_stake_msg(&rcv_msg.msg)?;
+    let (multiplier, lock_end) = match stake_msg.lock_duration {
+        Some(duration) => match LOCK_TIERS.may_load(deps.storage, duration)? {
+            Some(multiplier) => match env.block.time.seconds().checked_add(duration) {
+                Some(lock_end) => (multiplier, lock_end),
+                None => return Err(ContractError::InvalidLockTier {}),
+            },
+            None => return Err(ContractError::InvalidLockTier {}),
+        },
+        None => (DEFAULT_WEIGHT, 0),
+    };
 
-    Ok(Response::default())
-}
+    let token_weight = token_weight(deps.as_ref(), &sender, &rcv_msg.token_id)?;
+    let weight = staked_weight(token_weight, collection.reward_weight, multiplier)?;
+    let base_weight = staked_weight(token_weight, collection.reward_weight, DEFAULT_WEIGHT)?;
 
+    collection.total_staked += 1;
+    COLLECTIONS.save(deps.storage, &sender, &collection)?;
 
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+    state.total_staked += Uint128::new(1);
+    state.total_weight += Uint128::from(weight);
+    CONFIG.save(deps.storage, &state)?;
 
-fn execute_reward_wallet(
-    deps: DepsMut,
-    _env : Env,
-    info: MessageInfo,
-    address: String,
-)->Result<Response,ContractError>{
+    let token_info = TokenInfo {
+        owner: rcv_msg.sender.clone(),
+        token_id: rcv_msg.token_id.clone(),
+        status: "Staked".to_string(),
+        unstake_time: 0,
+        stake_time: env.block.time.seconds(),
+        reward: Uint128::new(0),
+        reward_checkpoint: state.reward_per_token,
+        nft_address: sender.clone(),
+        weight,
+        base_weight,
+        lock_end,
+    };
 
-    let state = CONFIG.load(deps.storage)?;
+    // streams must not pay the new token for anything distributed before it arrived
+    let streams = load_reward_streams(deps.storage)?;
+    let stream_rewards: Vec<StreamReward> = streams
+        .iter()
+        .map(|stream| StreamReward {
+            reward: Uint128::new(0),
+            reward_checkpoint: stream.reward_per_token,
+        })
+        .collect();
+    save_stream_rewards(deps.storage, &streams, &token_info, &stream_rewards)?;
 
-    if info.sender.to_string() != state.owner{
-        return Err(ContractError::Unauthorized {});
+    tokens().save(deps.storage, (&sender, &rcv_msg.token_id), &token_info)?;
+    if weight != base_weight {
+        LOCK_ENDS.save(deps.storage, (lock_end, &sender, &rcv_msg.token_id), &true)?;
     }
-    CONFIG.update(deps.storage,
-    |mut state|->StdResult<_>{
-        state.reward_wallet = address;
-        Ok(state)
-    })?;
+    record_activity(
+        deps.storage,
+        &rcv_msg.sender,
+        ActivityKind::Stake,
+        env.block.time.seconds(),
+        &token_info,
+        Uint128::new(0),
+    )?;
+
     Ok(Response::default())
 }
 
+/// Loads every listed token, making sure each one is held for `owner` and listed only once.
+fn load_owned_tokens(
+    storage: &dyn Storage,
+    owner: &str,
+    token_ids: &[TokenKey],
+) -> Result<Vec<TokenInfo>, ContractError> {
+    let mut token_infos: Vec<TokenInfo> = vec![];
+    for (index, key) in token_ids.iter().enumerate() {
+        if token_ids[..index].contains(key) {
+            return Err(ContractError::DuplicateToken {});
+        }
 
-fn execute_nft_address(
-    deps: DepsMut,
-    _env:Env,
-    info: MessageInfo,
-    address: String,
-) -> Result<Response, ContractError> {
-    let state = CONFIG.load(deps.storage)?;
-    deps.api.addr_validate(&address)?;
-   
-    if state.owner != info.sender.to_string() {
-        return Err(ContractError::Unauthorized {});
-    }
+        let token_info = match tokens().may_load(storage, (&key.nft_address, &key.token_id))? {
+            Some(token_info) => token_info,
+            None => return Err(ContractError::NotStaked {}),
+        };
 
-    CONFIG.update(deps.storage, 
-        |mut state| -> StdResult<_>{
-            state.nft_address.push(address);
-            Ok(state)
-        } )?;
+        if token_info.owner != owner {
+            return Err(ContractError::Unauthorized {});
+        }
 
-    Ok(Response::default())
+        token_infos.push(token_info);
+    }
+    Ok(token_infos)
 }
 
-fn execute_token_address(
+fn execute_unstake_nfts(
     deps: DepsMut,
-    _env:Env,
+    env: Env,
     info: MessageInfo,
-    address: String,
+    token_ids: Vec<TokenKey>,
 ) -> Result<Response, ContractError> {
     let mut state = CONFIG.load(deps.storage)?;
-    deps.api.addr_validate(&address)?;
-    state.token_address = address;
-    
-    if state.owner != info.sender.to_string() {
-        return Err(ContractError::Unauthorized {});
-    }
-
-    CONFIG.save(deps.storage, &state)?;
-    Ok(Response::default())
-}
 
+    if load_pause_state(deps.storage)?.unstaking {
+        return Err(ContractError::UnstakingPaused {});
+    }
+    let now = env.block.time.seconds();
+
+    // the tokens are loaded after their own expired locks have been written
+    expire_locks(deps.storage, &mut state, now)?;
+    let token_infos = load_owned_tokens(deps.storage, info.sender.as_str(), &token_ids)?;
+    for token_info in &token_infos {
+        if token_info.status != "Staked" {
+            return Err(ContractError::StatusError {});
+        }
 
-fn execute_set_owner(
-    deps: DepsMut,
-     _env:Env,
-    info: MessageInfo,
-    address: String,
-) -> Result<Response, ContractError> {
-    let mut state = CONFIG.load(deps.storage)?;
-    deps.api.addr_validate(&address)?;
-    state.owner = address;
-    
-    if state.owner != info.sender.to_string() {
-        return Err(ContractError::Unauthorized {});
+        if now < token_info.lock_end {
+            return Err(ContractError::Locked {
+                lock_end: token_info.lock_end,
+            });
+        }
     }
 
+    let streams = load_reward_streams(deps.storage)?;
+
+    for mut token_info in token_infos {
+        settle_reward(&state, &mut token_info);
+        let stream_rewards = settle_stream_rewards(deps.storage, &streams, &token_info)?;
+        save_stream_rewards(deps.storage, &streams, &token_info, &stream_rewards)?;
+
+        token_info.status = "Unstaking".to_string();
+        token_info.unstake_time = now;
+        tokens().save(
+            deps.storage,
+            (&token_info.nft_address, &token_info.token_id),
+            &token_info,
+        )?;
+        record_activity(
+            deps.storage,
+            info.sender.as_str(),
+            ActivityKind::Unstake,
+            now,
+            &token_info,
+            Uint128::new(0),
+        )?;
+
+        let mut collection = COLLECTIONS.load(deps.storage, &token_info.nft_address)?;
+        collection.total_staked -= 1;
+        COLLECTIONS.save(deps.storage, &token_info.nft_address, &collection)?;
+
+        state.total_staked -= Uint128::new(1);
+        state.total_weight -= Uint128::from(token_info.weight);
+    }
     CONFIG.save(deps.storage, &state)?;
-    Ok(Response::default())
-}
 
+    let mut stats = load_stats(deps.storage)?;
+    stats.unstaking_count += token_ids.len() as u64;
+    STATS.save(deps.storage, &stats)?;
 
+    Ok(Response::default())
+}
 
-fn execute_staking_period(
+fn execute_withdraw_nfts(
     deps: DepsMut,
-    _env : Env,
+    env: Env,
     info: MessageInfo,
-    time: u64,
-)->Result<Response,ContractError>{
-
+    token_ids: Vec<TokenKey>,
+) -> Result<Response, ContractError> {
     let state = CONFIG.load(deps.storage)?;
 
-    if info.sender.to_string() != state.owner{
-        return Err(ContractError::Unauthorized {});
+    if load_pause_state(deps.storage)?.withdrawing {
+        return Err(ContractError::WithdrawingPaused {});
     }
-    CONFIG.update(deps.storage,
-    |mut state|->StdResult<_>{
-        state.staking_period = time;
-        Ok(state)
-    })?;
-    Ok(Response::default())
-}
+    let now = env.block.time.seconds();
 
+    let token_infos = load_owned_tokens(deps.storage, info.sender.as_str(), &token_ids)?;
+    for token_info in &token_infos {
+        if token_info.status == "Staked" {
+            return Err(ContractError::StatusError {});
+        }
 
-fn execute_claim_amount(
-    deps: DepsMut,
-    _env : Env,
-    info: MessageInfo,
-    amount: Uint128,
-)->Result<Response,ContractError>{
+        let unbonding_period = COLLECTIONS
+            .load(deps.storage, &token_info.nft_address)?
+            .unbonding_period
+            .unwrap_or(state.staking_period);
+        if (now - token_info.unstake_time) < unbonding_period {
+            return Err(ContractError::TimeRemaining {});
+        }
+    }
 
-    let state = CONFIG.load(deps.storage)?;
+    let streams = load_reward_streams(deps.storage)?;
+    let mut amount = Uint128::new(0);
+    let mut stream_amounts = vec![Uint128::new(0); streams.len()];
+    let mut nft_messages: Vec<CosmosMsg> = vec![];
+
+    for mut token_info in token_infos {
+        settle_reward(&state, &mut token_info);
+        amount += token_info.reward;
+        let stream_rewards = settle_stream_rewards(deps.storage, &streams, &token_info)?;
+        for (total, stream_reward) in stream_amounts.iter_mut().zip(stream_rewards) {
+            *total += stream_reward.reward;
+        }
 
-    if info.sender.to_string() != state.owner{
-        return Err(ContractError::Unauthorized {});
+        remove_token(deps.storage, &streams, &token_info)?;
+        record_activity(
+            deps.storage,
+            info.sender.as_str(),
+            ActivityKind::Withdraw,
+            now,
+            &token_info,
+            token_info.reward,
+        )?;
+        nft_messages.push(nft_transfer(
+            &token_info.nft_address,
+            info.sender.as_str(),
+            &token_info.token_id,
+        )?);
     }
-    CONFIG.update(deps.storage,
-    |mut state|->StdResult<_>{
-        state.claim_reward = amount;
-        Ok(state)
-    })?;
-    Ok(Response::default())
-}
-
 
+    let mut stats = load_stats(deps.storage)?;
+    stats.unstaking_count -= token_ids.len() as u64;
+    stats.total_claimed += amount;
+    STATS.save(deps.storage, &stats)?;
+
+    let messages = reward_messages(
+        &state,
+        &streams,
+        info.sender.as_str(),
+        amount,
+        &stream_amounts,
+    )?;
+    Ok(Response::new()
+        .add_messages(nft_messages)
+        .add_messages(messages))
+}
 
-fn execute_distribute_period(
+fn execute_emergency_withdraw(
     deps: DepsMut,
-    _env : Env,
+    env: Env,
     info: MessageInfo,
-    time: u64,
-)->Result<Response,ContractError>{
-
-    let state = CONFIG.load(deps.storage)?;
+    nft_address: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
 
-    if info.sender.to_string() != state.owner{
-        return Err(ContractError::Unauthorized {});
+    if load_pause_state(deps.storage)?.withdrawing {
+        return Err(ContractError::WithdrawingPaused {});
     }
-    CONFIG.update(deps.storage,
-    |mut state|->StdResult<_>{
-        state.distribute_period = time;
-        Ok(state)
-    })?;
-    Ok(Response::default())
-}
-
 
+    let key = TokenKey {
+        nft_address,
+        token_id,
+    };
+    let token_ids = vec![key];
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+    let mut token_info =
+        load_owned_tokens(deps.storage, info.sender.as_str(), &token_ids)?.remove(0);
+
+    if env.block.time.seconds() < token_info.lock_end {
+        return Err(ContractError::Locked {
+            lock_end: token_info.lock_end,
+        });
+    }
 
-fn execute_set_stake(
-    deps: DepsMut,
-
-    info: MessageInfo,
-    flag: bool,
-)->Result<Response,ContractError>{
+    settle_reward(&state, &mut token_info);
+    let mut streams = load_reward_streams(deps.storage)?;
+    let stream_rewards = settle_stream_rewards(deps.storage, &streams, &token_info)?;
 
-    let state = CONFIG.load(deps.storage)?;
+    if token_info.status == "Staked" {
+        let mut collection = COLLECTIONS.load(deps.storage, &token_info.nft_address)?;
+        collection.total_staked -= 1;
+        COLLECTIONS.save(deps.storage, &token_info.nft_address, &collection)?;
 
-    if info.sender.to_string() != state.owner{
-        return Err(ContractError::Unauthorized {});
+        state.total_staked -= Uint128::new(1);
+        state.total_weight -= Uint128::from(token_info.weight);
     }
-    CONFIG.update(deps.storage,
-    |mut state|->StdResult<_>{
-        state.can_stake = flag;
-        Ok(state)
-    })?;
-    Ok(Response::default())
-}
 
+    let percent = Uint128::from(state.emergency_penalty);
+    let hundred = Uint128::new(100);
+    let penalty = token_info.reward.multiply_ratio(percent, hundred);
+    let amount = token_info.reward - penalty;
+    let mut stream_penalties: Vec<Uint128> = vec![];
+    let mut stream_amounts: Vec<Uint128> = vec![];
+    for stream_reward in &stream_rewards {
+        let stream_penalty = stream_reward.reward.multiply_ratio(percent, hundred);
+        stream_penalties.push(stream_penalty);
+        stream_amounts.push(stream_reward.reward - stream_penalty);
+    }
 
+    let mut messages = reward_messages(
+        &state,
+        &streams,
+        info.sender.as_str(),
+        amount,
+        &stream_amounts,
+    )?;
+    match &state.treasury {
+        Some(treasury) => {
+            messages.extend(reward_messages(
+                &state,
+                &streams,
+                treasury,
+                penalty,
+                &stream_penalties,
+            )?);
+        }
+        // the token has already left the totals, so forfeits only reach the remaining stakers
+        None => {
+            redistribute(
+                penalty,
+                &mut state.reward_per_token,
+                &mut state.reward_remainder,
+                state.total_weight,
+            )?;
+            for (stream, stream_penalty) in streams.iter_mut().zip(stream_penalties) {
+                redistribute(
+                    stream_penalty,
+                    &mut stream.reward_per_token,
+                    &mut stream.reward_remainder,
+                    state.total_weight,
+                )?;
+                REWARD_STREAMS.save(deps.storage, stream.id, stream)?;
+            }
+        }
+    }
+    CONFIG.save(deps.storage, &state)?;
 
+    let mut stats = load_stats(deps.storage)?;
+    if token_info.status != "Staked" {
+        stats.unstaking_count -= 1;
+    }
+    stats.total_claimed += amount;
+    STATS.save(deps.storage, &stats)?;
+
+    remove_token(deps.storage, &streams, &token_info)?;
+    record_activity(
+        deps.storage,
+        info.sender.as_str(),
+        ActivityKind::EmergencyWithdraw,
+        env.block.time.seconds(),
+        &token_info,
+        amount,
+    )?;
 
+    Ok(Response::new()
+        .add_message(nft_transfer(
+            &token_info.nft_address,
+            info.sender.as_str(),
+            &token_info.token_id,
+        )?)
+        .add_messages(messages))
+}
 
-fn execute_migrate_token(
+fn execute_cancel_unstake(
     deps: DepsMut,
-    _env : Env,
+    env: Env,
     info: MessageInfo,
-    amount: Uint128,
-    address:String,
-    id:Vec<String>
-)->Result<Response,ContractError>{
+    nft_address: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
 
-    deps.api.addr_validate(&address)?;
-    let state = CONFIG.load(deps.storage)?;
+    let key = TokenKey {
+        nft_address,
+        token_id,
+    };
+    let mut token_info = load_owned_tokens(deps.storage, info.sender.as_str(), &[key])?.remove(0);
 
-    if info.sender.to_string() != state.owner{
-        return Err(ContractError::Unauthorized {});
+    if token_info.status == "Staked" {
+        return Err(ContractError::StatusError {});
     }
 
-    let mut messages :Vec<CosmosMsg> = Vec::new();
+    // going back to staked counts as a new stake for the collection limits
+    if !state.can_stake {
+        return Err(ContractError::CanNotStake {});
+    }
 
+    let mut collection = COLLECTIONS.load(deps.storage, &token_info.nft_address)?;
+    if !collection.enabled {
+        return Err(ContractError::CollectionDisabled {});
+    }
 
-    for token_id in id{
-        let token_info = TOKENINFO.may_load(deps.storage, &token_id)?;
-        if token_info != None{
-            let token_info = token_info.unwrap();
-            messages.push(
-                CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: token_info.nft_address, 
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: address.clone(), 
-                    token_id: token_id 
-                    })? , 
-                funds: vec![] })
-            )
+    if let Some(max_staked) = collection.max_staked {
+        if collection.total_staked >= max_staked {
+            return Err(ContractError::CollectionFull {});
         }
     }
-   
-    Ok(Response::new()
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: state.token_address, 
-             msg: to_binary(&Cw20ExecuteMsg::Transfer {
-                 recipient: address, 
-                 amount: amount 
-                })? , 
-             funds: vec![] }))
-        .add_messages(messages)
-)
+
+    // settling while still unstaking moves the checkpoints past everything distributed in the
+    // meantime, so the token only earns from here on
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+    settle_reward(&state, &mut token_info);
+    let streams = load_reward_streams(deps.storage)?;
+    let stream_rewards = settle_stream_rewards(deps.storage, &streams, &token_info)?;
+    save_stream_rewards(deps.storage, &streams, &token_info, &stream_rewards)?;
+
+    token_info.status = "Staked".to_string();
+    token_info.unstake_time = 0;
+    tokens().save(
+        deps.storage,
+        (&token_info.nft_address, &token_info.token_id),
+        &token_info,
+    )?;
+    record_activity(
+        deps.storage,
+        info.sender.as_str(),
+        ActivityKind::CancelUnstake,
+        env.block.time.seconds(),
+        &token_info,
+        Uint128::new(0),
+    )?;
+
+    collection.total_staked += 1;
+    COLLECTIONS.save(deps.storage, &token_info.nft_address, &collection)?;
+
+    state.total_staked += Uint128::new(1);
+    state.total_weight += Uint128::from(token_info.weight);
+    CONFIG.save(deps.storage, &state)?;
+
+    let mut stats = load_stats(deps.storage)?;
+    stats.unstaking_count -= 1;
+    STATS.save(deps.storage, &stats)?;
+
+    Ok(Response::default())
 }
 
+fn execute_get_reward(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_ids: Vec<TokenKey>,
+) -> Result<Response, ContractError> {
+    if load_pause_state(deps.storage)?.claiming {
+        return Err(ContractError::ClaimingPaused {});
+    }
+
+    let mut state = CONFIG.load(deps.storage)?;
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+
+    let streams = load_reward_streams(deps.storage)?;
+    let mut amount = Uint128::new(0);
+    let mut stream_amounts = vec![Uint128::new(0); streams.len()];
+
+    let token_infos = load_owned_tokens(deps.storage, info.sender.as_str(), &token_ids)?;
+    for mut token_info in token_infos {
+        settle_reward(&state, &mut token_info);
+        amount += token_info.reward;
+        record_activity(
+            deps.storage,
+            info.sender.as_str(),
+            ActivityKind::Claim,
+            env.block.time.seconds(),
+            &token_info,
+            token_info.reward,
+        )?;
+        token_info.reward = Uint128::new(0);
+
+        let mut stream_rewards = settle_stream_rewards(deps.storage, &streams, &token_info)?;
+        for (total, stream_reward) in stream_amounts.iter_mut().zip(stream_rewards.iter_mut()) {
+            *total += stream_reward.reward;
+            stream_reward.reward = Uint128::new(0);
+        }
+        save_stream_rewards(deps.storage, &streams, &token_info, &stream_rewards)?;
 
+        tokens().save(
+            deps.storage,
+            (&token_info.nft_address, &token_info.token_id),
+            &token_info,
+        )?;
+    }
+    CONFIG.save(deps.storage, &state)?;
 
+    let mut stats = load_stats(deps.storage)?;
+    stats.total_claimed += amount;
+    STATS.save(deps.storage, &stats)?;
 
+    let messages = reward_messages(
+        &state,
+        &streams,
+        info.sender.as_str(),
+        amount,
+        &stream_amounts,
+    )?;
+    Ok(Response::new().add_messages(messages))
+}
 
+fn execute_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    rcv_msg: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    let msg: ReceiveMsg = from_binary(&rcv_msg.msg)?;
+
+    let token_address = match msg {
+        ReceiveMsg::DistributeStreamReward { stream_id } => {
+            match REWARD_STREAMS.load(deps.storage, stream_id)?.asset {
+                RewardAsset::Cw20 { address } => address,
+                RewardAsset::Native { .. } => return Err(ContractError::WrongTokenContract {}),
+            }
+        }
+        _ => state.token_address,
+    };
+
+    // `amount` is only trustworthy when the reward token contract itself reports the transfer.
+    if info.sender != token_address {
+        return Err(ContractError::WrongTokenContract {});
+    }
 
-#[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
-          QueryMsg::GetStateInfo {  } => to_binary(&query_state_info(deps)?),
-          QueryMsg::GetCurrentTime{} => to_binary(&query_get_current_time(deps,_env)?),
-          QueryMsg::GetToken { token_id } => to_binary(&query_get_token(deps,token_id)?),
-          QueryMsg::GetMyIds { address } => to_binary(&query_my_ids(deps,address)?),
-          QueryMsg::GetMyInfo { address }=> to_binary(&query_my_info(deps,address)?),
-  }
+        ReceiveMsg::DistributeReward {} => {
+            execute_distribute_reward(deps, env, rcv_msg.sender, rcv_msg.amount)
+        }
+        ReceiveMsg::FundEmission {} => {
+            execute_fund_emission(deps, env, rcv_msg.sender, rcv_msg.amount)
+        }
+        ReceiveMsg::DistributeStreamReward { stream_id } => {
+            execute_distribute_stream_reward(deps, env, rcv_msg.sender, rcv_msg.amount, stream_id)
+        }
+    }
 }
 
-pub fn query_state_info(deps:Deps) -> StdResult<State>{
-    let state =  CONFIG.load(deps.storage)?;
-    Ok(state)
-}
+fn execute_distribute_reward(
+    deps: DepsMut,
+    env: Env,
+    sender: String,
+    token_amount: Uint128,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
 
-pub fn query_get_current_time(_deps:Deps,env:Env) -> StdResult<u64>{
-    Ok(env.block.time.seconds())
+    if load_pause_state(deps.storage)?.distributing {
+        return Err(ContractError::DistributingPaused {});
+    }
+
+    if sender != state.reward_wallet {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if (env.block.time.seconds() - state.last_distribute) < state.distribute_period {
+        return Err(ContractError::CanNotDistribute {});
+    }
+
+    if state.total_staked == Uint128::new(0) {
+        return Err(ContractError::NotStaked {});
+    }
+
+    // Every unit of weight earns the same share, so bumping the global accumulator is enough;
+    // each token picks up its part in `settle_reward` the next time it is touched.
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+    let reward_per_token = split_reward(
+        token_amount,
+        &mut state.reward_remainder,
+        state.total_weight,
+    )?;
+    state.reward_per_token = state
+        .reward_per_token
+        .checked_add(reward_per_token)
+        .map_err(StdError::from)?;
+    state.last_distribute = env.block.time.seconds();
+    CONFIG.save(deps.storage, &state)?;
+
+    record_distribution(
+        deps.storage,
+        0,
+        state.last_distribute,
+        token_amount,
+        state.total_staked,
+        reward_per_token,
+    )?;
+
+    let mut stats = load_stats(deps.storage)?;
+    stats.total_distributed += token_amount;
+    STATS.save(deps.storage, &stats)?;
+
+    Ok(Response::default())
 }
 
-pub fn query_get_members(deps:Deps) -> StdResult<Vec<String>>{
-     let token_id :StdResult<Vec<String>>  = TOKENINFO
-        .keys(deps.storage, None, None, Order::Ascending)
-        .collect();
-    Ok(token_id?)
+fn execute_distribute_stream_reward(
+    deps: DepsMut,
+    env: Env,
+    sender: String,
+    token_amount: Uint128,
+    stream_id: u64,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+    let mut stream = REWARD_STREAMS.load(deps.storage, stream_id)?;
+
+    if load_pause_state(deps.storage)?.distributing {
+        return Err(ContractError::DistributingPaused {});
+    }
+
+    if sender != stream.funder {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if state.total_staked == Uint128::new(0) {
+        return Err(ContractError::NotStaked {});
+    }
+
+    // locks that ran out must not take a share of it
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+    CONFIG.save(deps.storage, &state)?;
+
+    let reward_per_token = split_reward(
+        token_amount,
+        &mut stream.reward_remainder,
+        state.total_weight,
+    )?;
+    stream.reward_per_token = stream
+        .reward_per_token
+        .checked_add(reward_per_token)
+        .map_err(StdError::from)?;
+    REWARD_STREAMS.save(deps.storage, stream_id, &stream)?;
+    record_distribution(
+        deps.storage,
+        stream_id,
+        env.block.time.seconds(),
+        token_amount,
+        state.total_staked,
+        reward_per_token,
+    )?;
+
+    Ok(Response::default())
 }
 
-pub fn query_token_info(deps:Deps)->StdResult<Vec<TokenInfo>>{
-      let res: StdResult<Vec<TokenInfo>> = TOKENINFO
-        .range(deps.storage, None, None, Order::Ascending)
-        .map(|kv_item|parse_token_info(kv_item))
-        .collect();
-    Ok(res?)
+fn execute_distribute_native_reward(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    stream_id: u64,
+) -> Result<Response, ContractError> {
+    let stream = REWARD_STREAMS.load(deps.storage, stream_id)?;
+
+    let denom = match stream.asset {
+        RewardAsset::Native { denom } => denom,
+        RewardAsset::Cw20 { .. } => return Err(ContractError::WrongTokenContract {}),
+    };
+
+    // anything but a single coin of the stream denom would end up stuck in the contract
+    let token_amount = match info.funds.as_slice() {
+        [coin] if coin.denom == denom && !coin.amount.is_zero() => coin.amount,
+        _ => return Err(ContractError::Notenough {}),
+    };
+
+    execute_distribute_stream_reward(deps, env, info.sender.to_string(), token_amount, stream_id)
 }
 
+fn execute_fund_emission(
+    deps: DepsMut,
+    env: Env,
+    sender: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
 
-fn parse_token_info(
-    item: StdResult<(String,TokenInfo)>,
-) -> StdResult<TokenInfo> {
-    item.and_then(|(_k, token_info)| {
-        Ok(token_info)
-    })
+    if load_pause_state(deps.storage)?.distributing {
+        return Err(ContractError::DistributingPaused {});
+    }
+
+    if sender != state.reward_wallet {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // seconds that passed while the balance was empty have already emitted nothing
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+    state.emission_balance += amount;
+    CONFIG.save(deps.storage, &state)?;
+
+    let mut stats = load_stats(deps.storage)?;
+    stats.total_emission_funded += amount;
+    STATS.save(deps.storage, &stats)?;
+
+    Ok(Response::default())
 }
 
+fn execute_reward_emission(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    rate: Uint128,
+    start_time: u64,
+    end_time: u64,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
 
-pub fn query_get_token(deps:Deps,token_id:String) -> StdResult<TokenInfo>{
-    let token_info = TOKENINFO.load(deps.storage,&token_id)?;
-    Ok(token_info)
+    check_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        Role::Distributor,
+    )?;
+
+    if end_time < start_time {
+        return Err(ContractError::InvalidEmission {});
+    }
+
+    // everything emitted under the old schedule is settled before the new one applies
+    expire_locks(deps.storage, &mut state, env.block.time.seconds())?;
+    state.emission_rate = rate;
+    state.emission_start = start_time;
+    state.emission_end = end_time;
+    CONFIG.save(deps.storage, &state)?;
+
+    Ok(Response::default())
 }
 
-pub fn query_my_ids(deps:Deps,address:String) -> StdResult<Vec<String>>{
-    let my_ids = OWNEDTOKEN.may_load(deps.storage,&address)?;
-    if my_ids == None{
-        Ok(vec![])
+fn execute_add_reward_stream(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: RewardAsset,
+    funder: String,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    deps.api.addr_validate(&funder)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match &asset {
+        RewardAsset::Cw20 { address } => {
+            deps.api.addr_validate(address)?;
+        }
+        RewardAsset::Native { denom } => {
+            if *denom != state.denom {
+                return Err(ContractError::WrongDenom { denom: state.denom });
+            }
+        }
     }
-    else{
-        Ok(my_ids.unwrap())
+
+    // id 0 is the primary stream kept in `State`
+    let id = STREAM_COUNT.may_load(deps.storage)?.unwrap_or_default() + 1;
+    if id > MAX_REWARD_STREAMS {
+        return Err(ContractError::TooManyRewardStreams {
+            max: MAX_REWARD_STREAMS,
+        });
     }
+    STREAM_COUNT.save(deps.storage, &id)?;
+
+    let stream = RewardStream {
+        id,
+        asset,
+        funder,
+        reward_per_token: Uint128::new(0),
+        reward_remainder: Uint128::new(0),
+    };
+    REWARD_STREAMS.save(deps.storage, id, &stream)?;
+
+    Ok(Response::new().add_attribute("stream_id", id.to_string()))
 }
 
-pub fn query_my_info(deps:Deps,address:String) -> StdResult<Vec<TokenInfo>>{
-    let my_ids = OWNEDTOKEN.may_load(deps.storage,&address)?;
-    if my_ids == None{
-        Ok(vec![])
+fn execute_trait_weight(
+    deps: DepsMut,
+    info: MessageInfo,
+    nft_address: String,
+    trait_type: String,
+    value: String,
+    weight: Option<u64>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
     }
-    else{
-        let mut my_nfts:Vec<TokenInfo> = vec![];
-        for id in my_ids.unwrap(){            
-            let token_info = TOKENINFO.load(deps.storage, &id)?;
-            my_nfts.push(token_info);         
+
+    let key = (nft_address.as_str(), trait_type.as_str(), value.as_str());
+    match weight {
+        Some(weight) => {
+            check_weight(weight)?;
+            TRAIT_WEIGHTS.save(deps.storage, key, &weight)?
         }
-          Ok(my_nfts)
+        None => TRAIT_WEIGHTS.remove(deps.storage, key),
     }
+    Ok(Response::default())
 }
 
+fn execute_token_weight(
+    deps: DepsMut,
+    info: MessageInfo,
+    nft_address: String,
+    token_id: String,
+    weight: Option<u64>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
 
-#[cfg(test)]
-mod tests {
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
 
-    use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{CosmosMsg};
+    let key = (nft_address.as_str(), token_id.as_str());
+    match weight {
+        Some(weight) => {
+            check_weight(weight)?;
+            TOKEN_WEIGHTS.save(deps.storage, key, &weight)?
+        }
+        None => TOKEN_WEIGHTS.remove(deps.storage, key),
+    }
+    Ok(Response::default())
+}
 
-    #[test]
-    fn testing() {
-        let mut deps = mock_dependencies();
-        let instantiate_msg = InstantiateMsg {
-            denom : "ujuno".to_string(),
-            staking_period : 1000,
-            reward_wallet :"reward_wallet".to_string(),
-            distribute_period:100,
-            token_address:"token_address".to_string(),
-            nft_address:vec!["nft_address".to_string()],
-            claim_reward:Uint128::new(500)
-        };
-        let info = mock_info("creator", &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
+fn execute_lock_tier(
+    deps: DepsMut,
+    info: MessageInfo,
+    duration: u64,
+    multiplier: Option<u64>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
 
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
 
-        let state = query_state_info(deps.as_ref()).unwrap();
-        assert_eq!(state,State{
-            nft_address :vec!["nft_address".to_string()],
-            token_address : "token_address".to_string(),
-            owner:"creator".to_string(),
-            staking_period : 1000,
-            denom : "ujuno".to_string(),
-            reward_wallet:"reward_wallet".to_string(),
-            total_staked:Uint128::new(0),
-            can_stake : true,
-            last_distribute : mock_env().block.time.seconds(),
-            distribute_period:100,
-            claim_reward:Uint128::new(500)
-        });
+    match multiplier {
+        Some(multiplier) => {
+            check_weight(multiplier)?;
+            LOCK_TIERS.save(deps.storage, duration, &multiplier)?
+        }
+        None => LOCK_TIERS.remove(deps.storage, duration),
+    }
+    Ok(Response::default())
+}
 
-        println!("{:?}","add nft address");
+fn execute_collection_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    reward_weight: u64,
+    enabled: bool,
+    max_staked: Option<u64>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
 
-        let info = mock_info("creator", &[]);
-        let msg = ExecuteMsg::AddNftAddress { address:"nft_address1".to_string() };
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
+    check_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        Role::CollectionManager,
+    )?;
 
-        println!("{:?}","add token address");
+    check_weight(reward_weight)?;
 
-        let info = mock_info("creator", &[]);
-        let msg = ExecuteMsg::SetTokenAddress { address:"token_address1".to_string() };
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
+    let mut collection = match COLLECTIONS.may_load(deps.storage, &address)? {
+        Some(collection) => collection,
+        None => return Err(ContractError::WrongNftContract {}),
+    };
+    collection.reward_weight = reward_weight;
+    collection.enabled = enabled;
+    collection.max_staked = max_staked;
+    COLLECTIONS.save(deps.storage, &address, &collection)?;
 
-        let state = query_state_info(deps.as_ref()).unwrap();
-        assert_eq!(state.nft_address,vec!["nft_address".to_string(),"nft_address1".to_string()]);
-        assert_eq!(state.token_address, "token_address1".to_string());
+    Ok(Response::default())
+}
 
-        println!("{:?}","set reward wallet");
+fn execute_nft_address(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    deps.api.addr_validate(&address)?;
 
-        let info = mock_info("creator", &[]);
-        let msg = ExecuteMsg::SetRewardWallet { address:"reward_wallet1".to_string() };
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
+    check_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        Role::CollectionManager,
+    )?;
 
-         println!("{:?}","set distribute period");
+    if state.nft_address.contains(&address) {
+        return Err(ContractError::DuplicateNftContract {});
+    }
 
+    register_collection(deps.storage, &address)?;
+    CONFIG.update(deps.storage, |mut state| -> StdResult<_> {
+        state.nft_address.push(address);
+        Ok(state)
+    })?;
 
-        let info = mock_info("creator", &[]);
-        let msg = ExecuteMsg::SetDistributePeriod { time:150 };
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
+    Ok(Response::default())
+}
 
-        let state= query_state_info(deps.as_ref()).unwrap();
-        assert_eq!(state.distribute_period,150);
+fn execute_remove_nft_address(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
 
-        let state = query_state_info(deps.as_ref()).unwrap();
-        assert_eq!(state.reward_wallet,"reward_wallet1".to_string());
-       
-         println!("{:?}","set staking period");
+    check_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        Role::CollectionManager,
+    )?;
 
+    if !state.nft_address.contains(&address) {
+        return Err(ContractError::WrongNftContract {});
+    }
 
-        let info = mock_info("creator", &[]);
-        let msg = ExecuteMsg::SetStakingPeriod { time: 1200 };
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
-        
-        let state = query_state_info(deps.as_ref()).unwrap();
-        assert_eq!(state.staking_period,1200);
+    // unbonding tokens still need the collection's unbonding period to be withdrawn
+    let holds_tokens = tokens()
+        .prefix(&address)
+        .keys_raw(deps.storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if holds_tokens {
+        return Err(ContractError::CollectionInUse {});
+    }
 
-        let info = mock_info("nft_address", &[]);
-        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
-            sender:"owner1".to_string(),
-            token_id : "reveal1".to_string(),
-            msg : to_binary(&"abc".to_string()).unwrap()
-        });
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
+    state
+        .nft_address
+        .retain(|nft_address| *nft_address != address);
+    CONFIG.save(deps.storage, &state)?;
+    COLLECTIONS.remove(deps.storage, &address);
 
-        let info = mock_info("nft_address1", &[]);
-        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
-            sender:"owner1".to_string(),
-            token_id : "reveal2".to_string(),
-            msg : to_binary(&"abc".to_string()).unwrap()
-        });
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
+    Ok(Response::default())
+}
 
-        // let my_nfs = query_my_ids(deps,"")
+fn execute_disable_nft_address(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
 
-        let tokens = query_get_members(deps.as_ref()).unwrap();
-        assert_eq!(tokens,vec!["reveal1","reveal2"]);
+    check_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        Role::CollectionManager,
+    )?;
 
-        let my_ids = query_my_ids(deps.as_ref(), "owner2".to_string()).unwrap();
-        let eq_my_ids:Vec<String> = vec![];
-        assert_eq!(my_ids,eq_my_ids);
+    let mut collection = match COLLECTIONS.may_load(deps.storage, &address)? {
+        Some(collection) => collection,
+        None => return Err(ContractError::WrongNftContract {}),
+    };
+    collection.enabled = false;
+    COLLECTIONS.save(deps.storage, &address, &collection)?;
 
-        println!("{:?}","tokens of owner1");
+    Ok(Response::default())
+}
 
-        let my_ids = query_my_ids(deps.as_ref(), "owner1".to_string()).unwrap();
-        assert_eq!(my_ids,["reveal1","reveal2"]);
-
-        println!("{:?}","tokens of owner2");
-
-        let my_token_infos = query_my_info(deps.as_ref(),"owner2".to_string()).unwrap();
-        let eq_my_ids:Vec<TokenInfo> = vec![];
-        assert_eq!(my_token_infos,eq_my_ids);
-
-        println!("{:?}","token informations of owner1");
-
-        let my_token_infos = query_my_info(deps.as_ref(),"owner1".to_string()).unwrap();
-        assert_eq!(my_token_infos,vec![TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal1".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Staked".to_string(),
-            reward:Uint128::new(0),
-            unstake_time :0,
-            nft_address:"nft_address".to_string()
-        },TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal2".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Staked".to_string(),
-            reward:Uint128::new(0),
-            unstake_time :0,
-            nft_address:"nft_address1".to_string()
-        }]);
-      
-       println!("{:?}","unstake reveal1");
-
-
-        let info = mock_info("owner1", &[]);
-        let msg = ExecuteMsg::UnstakeNft { token_id : "reveal1".to_string() };
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
-
-        let state =  query_state_info(deps.as_ref()).unwrap();
-
-        assert_eq!(state.total_staked,Uint128::new(1));
-
-        let tokens = query_get_members(deps.as_ref()).unwrap();
-        assert_eq!(tokens,vec!["reveal1","reveal2"]);
-
-        let token_infos = query_token_info(deps.as_ref()).unwrap();
-        assert_eq!(token_infos,vec![TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal1".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Unstaking".to_string(),
-            reward:Uint128::new(0),
-            unstake_time : mock_env().block.time.seconds(),
-             nft_address:"nft_address".to_string()
-        },TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal2".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Staked".to_string(),
-            reward:Uint128::new(0),
-            unstake_time :0,
-             nft_address:"nft_address1".to_string()
-        }]);
-
-        let my_token_infos = query_my_info(deps.as_ref(),"owner1".to_string()).unwrap();
-        assert_eq!(my_token_infos,vec![TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal1".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Unstaking".to_string(),
-            reward:Uint128::new(0),
-            unstake_time : mock_env().block.time.seconds(),
-             nft_address:"nft_address".to_string()
-        },TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal2".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Staked".to_string(),
-            reward:Uint128::new(0),
-            unstake_time :0,
-             nft_address:"nft_address1".to_string()
-        }]);
-
-
-        let info = mock_info("reward_wallet1", &[]);     
-        let msg = ExecuteMsg::DistributeReward { token_amount:Uint128::new(10)  };
-        execute(deps.as_mut(),mock_env(),info,msg).unwrap();
-
-        println!("{:?}","check the reward distribution");
-
-        let token_infos = query_token_info(deps.as_ref()).unwrap();
-        assert_eq!(token_infos,vec![TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal1".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Unstaking".to_string(),
-            reward:Uint128::new(0),
-            unstake_time : mock_env().block.time.seconds(),
-            nft_address:"nft_address".to_string()
-        },TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal2".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Staked".to_string(),
-            reward:Uint128::new(10),
-            unstake_time :0,
-            nft_address:"nft_address1".to_string()
-        }]);
-
-        let info = mock_info("owner1", &[]);     
-        let msg = ExecuteMsg::GetReward { token_ids:vec!["reveal1".to_string(),"reveal2".to_string()] };
-        let res = execute(deps.as_mut(),mock_env(),info,msg).unwrap();
-        assert_eq!(1,res.messages.len());
-        assert_eq!(res.messages[0].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: "token_address1".to_string(), 
-             msg: to_binary(&Cw20ExecuteMsg::Transfer {
-                 recipient: "owner1".to_string(), 
-                 amount: Uint128::new(10) 
-                }).unwrap() , 
-             funds: vec![] }));
-
-       
-        
-        let info = mock_info("owner1", &[]);     
-        let msg = ExecuteMsg::WithdrawNft { token_id:"reveal1".to_string() };
-        let res = execute(deps.as_mut(),mock_env(),info,msg).unwrap();
-        
-        
-        let my_ids = query_my_ids(deps.as_ref(), "owner1".to_string()).unwrap();
-        assert_eq!(my_ids,["reveal2"]);
-
-        assert_eq!(1,res.messages.len());
-        assert_eq!(res.messages[0].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: "nft_address".to_string(), 
-             msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                  recipient: "owner1".to_string(), 
-                  token_id: "reveal1".to_string() }).unwrap() , 
-             funds: vec![] }));
-
-        let tokens = query_get_members(deps.as_ref()).unwrap();
-        assert_eq!(tokens,vec!["reveal2"]);
-
-        let id_info = query_get_token(deps.as_ref(),"reveal2".to_string()).unwrap();
-        assert_eq!(id_info,TokenInfo{
-            owner:"owner1".to_string(),
-            token_id:"reveal2".to_string(),
-            stake_time:mock_env().block.time.seconds(),
-            status:"Staked".to_string(),
-            reward:Uint128::new(0),
-            unstake_time :0,
-            nft_address:"nft_address1".to_string()
-        });
+/// Fails unless `sender` may queue, execute and cancel `action`.
+fn check_action_role(
+    storage: &dyn Storage,
+    state: &State,
+    sender: &str,
+    action: &TimelockedAction,
+) -> Result<(), ContractError> {
+    match action {
+        TimelockedAction::SetRewardWallet { .. } | TimelockedAction::SetEmergencyPenalty { .. } => {
+            check_role(storage, state, sender, Role::Treasurer)
+        }
+        TimelockedAction::SetStakingPeriod { .. } | TimelockedAction::SetUnbondingPeriod { .. } => {
+            check_role(storage, state, sender, Role::CollectionManager)
+        }
+        TimelockedAction::SetTokenAddress { .. } | TimelockedAction::SetTimelockDelay { .. } => {
+            if sender != state.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+            Ok(())
+        }
+    }
+}
 
-      let info = mock_info("creator", &[]);     
-      let msg = ExecuteMsg::Migrate { amount: Uint128::new(10), address: "new_staking".to_string(), id: vec!["reveal1".to_string(),"reveal2".to_string()] };
-      let res = execute(deps.as_mut(),mock_env(),info,msg).unwrap();
-      assert_eq!(res.messages.len(),2);
-      
-      assert_eq!(res.messages[1].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: "nft_address1".to_string(), 
-             msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                  recipient: "new_staking".to_string(), 
-                  token_id: "reveal2".to_string() }).unwrap() , 
-             funds: vec![] }));
-
-       assert_eq!(res.messages[0].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-             contract_addr: "token_address1".to_string(), 
-             msg: to_binary(&Cw20ExecuteMsg::Transfer {
-                 recipient: "new_staking".to_string(), 
-                 amount: Uint128::new(10) 
-                }).unwrap() , 
-        funds: vec![] }));
-
-    
+/// Fails if `action` could not be applied as it stands.
+fn check_action(deps: Deps, action: &TimelockedAction) -> Result<(), ContractError> {
+    match action {
+        TimelockedAction::SetTokenAddress { address } => {
+            deps.api.addr_validate(address)?;
+        }
+        TimelockedAction::SetTimelockDelay { delay } if *delay > MAX_TIMELOCK_DELAY => {
+            return Err(ContractError::InvalidTimelockDelay {
+                max: MAX_TIMELOCK_DELAY,
+            });
+        }
+        TimelockedAction::SetUnbondingPeriod { address, .. }
+            if !COLLECTIONS.has(deps.storage, address) =>
+        {
+            return Err(ContractError::WrongNftContract {});
+        }
+        TimelockedAction::SetEmergencyPenalty { percent, .. } if *percent > 100 => {
+            return Err(ContractError::InvalidPenalty {});
+        }
+        TimelockedAction::SetEmergencyPenalty {
+            treasury: Some(treasury),
+            ..
+        } => {
+            deps.api.addr_validate(treasury)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn apply_action(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    action: TimelockedAction,
+) -> Result<(), ContractError> {
+    match action {
+        TimelockedAction::SetTokenAddress { address } => state.token_address = address,
+        TimelockedAction::SetRewardWallet { address } => state.reward_wallet = address,
+        TimelockedAction::SetStakingPeriod { time } => state.staking_period = time,
+        TimelockedAction::SetTimelockDelay { delay } => state.timelock_delay = delay,
+        TimelockedAction::SetUnbondingPeriod {
+            address,
+            unbonding_period,
+        } => {
+            // the collection may have been removed while the change was queued
+            let mut collection = match COLLECTIONS.may_load(storage, &address)? {
+                Some(collection) => collection,
+                None => return Err(ContractError::WrongNftContract {}),
+            };
+            collection.unbonding_period = unbonding_period;
+            COLLECTIONS.save(storage, &address, &collection)?;
+        }
+        TimelockedAction::SetEmergencyPenalty { percent, treasury } => {
+            state.emergency_penalty = percent;
+            state.treasury = treasury;
+        }
+    }
+    Ok(())
+}
+
+fn execute_queue_action(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    action: TimelockedAction,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+
+    check_action_role(deps.storage, &state, info.sender.as_str(), &action)?;
+    check_action(deps.as_ref(), &action)?;
+
+    if state.timelock_delay == 0 {
+        apply_action(deps.storage, &mut state, action)?;
+        CONFIG.save(deps.storage, &state)?;
+        return Ok(Response::default());
+    }
+
+    let eta = match env.block.time.seconds().checked_add(state.timelock_delay) {
+        Some(eta) => eta,
+        None => {
+            return Err(ContractError::InvalidTimelockDelay {
+                max: MAX_TIMELOCK_DELAY,
+            })
+        }
+    };
+    let id = QUEUED_ACTION_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default()
+        + 1;
+    QUEUED_ACTION_COUNT.save(deps.storage, &id)?;
+
+    let queued_action = QueuedAction { id, action, eta };
+    QUEUED_ACTIONS.save(deps.storage, id, &queued_action)?;
+
+    Ok(Response::new().add_attribute("action_id", id.to_string()))
+}
+
+fn execute_queued_action(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+    let queued_action = match QUEUED_ACTIONS.may_load(deps.storage, id)? {
+        Some(queued_action) => queued_action,
+        None => return Err(ContractError::NoQueuedAction { id }),
+    };
+
+    check_action_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        &queued_action.action,
+    )?;
+
+    if env.block.time.seconds() < queued_action.eta {
+        return Err(ContractError::TimeRemaining {});
+    }
+
+    QUEUED_ACTIONS.remove(deps.storage, id);
+    apply_action(deps.storage, &mut state, queued_action.action)?;
+    CONFIG.save(deps.storage, &state)?;
+
+    Ok(Response::default())
+}
+
+fn execute_cancel_queued_action(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    let queued_action = match QUEUED_ACTIONS.may_load(deps.storage, id)? {
+        Some(queued_action) => queued_action,
+        None => return Err(ContractError::NoQueuedAction { id }),
+    };
+
+    check_action_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        &queued_action.action,
+    )?;
+
+    QUEUED_ACTIONS.remove(deps.storage, id);
+    Ok(Response::default())
+}
+
+fn execute_propose_owner(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    address: String,
+    expires_at: Option<u64>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    deps.api.addr_validate(&address)?;
+
+    if state.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if matches!(expires_at, Some(expires_at) if expires_at <= env.block.time.seconds()) {
+        return Err(ContractError::OwnershipExpired {});
+    }
+
+    PENDING_OWNER.save(
+        deps.storage,
+        &PendingOwner {
+            address,
+            expires_at,
+        },
+    )?;
+    Ok(Response::default())
+}
+
+fn execute_accept_ownership(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let pending_owner = match PENDING_OWNER.may_load(deps.storage)? {
+        Some(pending_owner) => pending_owner,
+        None => return Err(ContractError::NoPendingOwner {}),
+    };
+
+    if pending_owner.address != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if matches!(pending_owner.expires_at, Some(expires_at) if expires_at <= env.block.time.seconds())
+    {
+        return Err(ContractError::OwnershipExpired {});
+    }
+
+    PENDING_OWNER.remove(deps.storage);
+    CONFIG.update(deps.storage, |mut state| -> StdResult<_> {
+        state.owner = pending_owner.address;
+        Ok(state)
+    })?;
+    Ok(Response::default())
+}
+
+fn execute_cancel_ownership_transfer(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    if state.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if PENDING_OWNER.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoPendingOwner {});
+    }
+
+    PENDING_OWNER.remove(deps.storage);
+    Ok(Response::default())
+}
+
+fn execute_grant_role(
+    deps: DepsMut,
+    info: MessageInfo,
+    role: Role,
+    address: String,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    deps.api.addr_validate(&address)?;
+
+    if state.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    ROLES.save(deps.storage, (role.as_str(), &address), &true)?;
+    Ok(Response::default())
+}
+
+fn execute_revoke_role(
+    deps: DepsMut,
+    info: MessageInfo,
+    role: Role,
+    address: String,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    if state.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    ROLES.remove(deps.storage, (role.as_str(), &address));
+    Ok(Response::default())
+}
+
+fn execute_claim_amount(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    check_role(deps.storage, &state, info.sender.as_str(), Role::Treasurer)?;
+    CONFIG.update(deps.storage, |mut state| -> StdResult<_> {
+        state.claim_reward = amount;
+        Ok(state)
+    })?;
+    Ok(Response::default())
+}
+
+fn execute_distribute_period(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    time: u64,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    check_role(
+        deps.storage,
+        &state,
+        info.sender.as_str(),
+        Role::Distributor,
+    )?;
+    CONFIG.update(deps.storage, |mut state| -> StdResult<_> {
+        state.distribute_period = time;
+        Ok(state)
+    })?;
+    Ok(Response::default())
+}
+
+fn execute_set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    switch: PauseSwitch,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+
+    check_role(deps.storage, &state, info.sender.as_str(), Role::Pauser)?;
+
+    let mut pause_state = load_pause_state(deps.storage)?;
+    match switch {
+        PauseSwitch::Staking => {
+            state.can_stake = !paused;
+            CONFIG.save(deps.storage, &state)?;
+            return Ok(Response::default());
+        }
+        PauseSwitch::Unstaking => pause_state.unstaking = paused,
+        PauseSwitch::Withdrawing => pause_state.withdrawing = paused,
+        PauseSwitch::Claiming => pause_state.claiming = paused,
+        PauseSwitch::Distributing => pause_state.distributing = paused,
+    }
+    PAUSE_STATE.save(deps.storage, &pause_state)?;
+    Ok(Response::default())
+}
+
+fn execute_migrate_token(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    address: String,
+    id: Vec<TokenKey>,
+) -> Result<Response, ContractError> {
+    deps.api.addr_validate(&address)?;
+    let state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut messages: Vec<CosmosMsg> = Vec::new();
+
+    for key in id {
+        if tokens()
+            .may_load(deps.storage, (&key.nft_address, &key.token_id))?
+            .is_some()
+        {
+            messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: key.nft_address,
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: address.clone(),
+                    token_id: key.token_id,
+                })?,
+                funds: vec![],
+            }))
+        }
+    }
+
+    Ok(Response::new()
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: state.token_address,
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: address,
+                amount,
+            })?,
+            funds: vec![],
+        }))
+        .add_messages(messages))
+}
+
+#[entry_point]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    let stored_version: Version = stored.version.parse()?;
+    let version: Version = CONTRACT_VERSION.parse()?;
+
+    // v0.10.0 is the first release whose storage layout we know how to read.
+    if stored.contract != CONTRACT_NAME
+        || stored_version < Version::new(0, 10, 0)
+        || stored_version > version
+    {
+        return Err(ContractError::InvalidMigration {
+            contract: stored.contract,
+            version: stored.version,
+        });
+    }
+
+    if stored_version < Version::new(0, 11, 0) {
+        migrate_state_from_v0_10(deps.storage)?;
+        migrate_tokens_from_v0_10(deps.storage)?;
+        migrate_collections_from_v0_10(deps.storage)?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::default())
+}
+
+/// v0.11.0 added the reward accumulator, the emission schedule and token weights. Nothing was
+/// distributed through either yet, so the accumulator starts at zero together with every token
+/// checkpoint, and every token already staked gets `DEFAULT_WEIGHT`.
+fn migrate_state_from_v0_10(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy_state = LEGACY_CONFIG.load(storage)?;
+    let state = State {
+        owner: legacy_state.owner,
+        denom: legacy_state.denom,
+        staking_period: legacy_state.staking_period,
+        reward_wallet: legacy_state.reward_wallet,
+        total_staked: legacy_state.total_staked,
+        nft_address: legacy_state.nft_address,
+        token_address: legacy_state.token_address,
+        can_stake: legacy_state.can_stake,
+        last_distribute: legacy_state.last_distribute,
+        distribute_period: legacy_state.distribute_period,
+        claim_reward: legacy_state.claim_reward,
+        reward_per_token: Uint128::new(0),
+        emission_rate: Uint128::new(0),
+        emission_start: 0,
+        emission_end: 0,
+        emission_balance: Uint128::new(0),
+        last_emission: legacy_state.last_distribute,
+        reward_remainder: Uint128::new(0),
+        total_weight: legacy_state.total_staked * Uint128::from(DEFAULT_WEIGHT),
+        emergency_penalty: 0,
+        treasury: None,
+        timelock_delay: 0,
+    };
+    CONFIG.save(storage, &state)
+}
+
+/// v0.11.0 keys tokens by `(nft_address, token_id)` instead of `token_id` alone, and counts the
+/// unstaking ones for `Stats`.
+fn migrate_tokens_from_v0_10(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy_tokens = LEGACY_TOKENINFO
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let stats = Stats {
+        unstaking_count: legacy_tokens
+            .iter()
+            .filter(|(_, legacy_token)| legacy_token.status == "Unstaking")
+            .count() as u64,
+        ..Stats::default()
+    };
+    STATS.save(storage, &stats)?;
+
+    // Owned lists are not kept anymore, the owner index is built from the tokens themselves. This
+    // also drops entries the old layout left behind for withdrawn tokens.
+    let owners = LEGACY_OWNEDTOKEN
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for owner in owners {
+        LEGACY_OWNEDTOKEN.remove(storage, &owner);
+    }
+
+    for (token_id, legacy_token) in legacy_tokens {
+        let token_info = TokenInfo {
+            owner: legacy_token.owner,
+            token_id: legacy_token.token_id,
+            status: legacy_token.status,
+            unstake_time: legacy_token.unstake_time,
+            stake_time: legacy_token.stake_time,
+            reward: legacy_token.reward,
+            reward_checkpoint: Uint128::new(0),
+            nft_address: legacy_token.nft_address,
+            weight: DEFAULT_WEIGHT,
+            base_weight: DEFAULT_WEIGHT,
+            lock_end: 0,
+        };
+
+        tokens().save(
+            storage,
+            (&token_info.nft_address, &token_info.token_id),
+            &token_info,
+        )?;
+        LEGACY_TOKENINFO.remove(storage, &token_id);
+    }
+
+    Ok(())
+}
+
+/// v0.11.0 keeps staking parameters per collection. Every allowed collection starts from the
+/// global defaults, with its staked tokens counted from the migrated ones.
+fn migrate_collections_from_v0_10(storage: &mut dyn Storage) -> StdResult<()> {
+    let state = CONFIG.load(storage)?;
+    for address in &state.nft_address {
+        register_collection(storage, address)?;
+        let mut collection = COLLECTIONS.load(storage, address)?;
+        collection.total_staked = tokens()
+            .prefix(address)
+            .range(storage, None, None, Order::Ascending)
+            .filter(|item| matches!(item, Ok((_, token_info)) if token_info.status == "Staked"))
+            .count() as u64;
+        COLLECTIONS.save(storage, address, &collection)?;
+    }
+    Ok(())
+}
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetStateInfo {} => to_binary(&query_state_info(deps)?),
+        QueryMsg::GetCurrentTime {} => to_binary(&query_get_current_time(deps, env)?),
+        QueryMsg::GetToken {
+            nft_address,
+            token_id,
+        } => to_binary(&query_get_token(deps, env, nft_address, token_id)?),
+        QueryMsg::GetMyIds { address } => to_binary(&query_my_ids(deps, address)?),
+        QueryMsg::GetMyInfo { address } => to_binary(&query_my_info(deps, env, address)?),
+        QueryMsg::GetRewardStreams {} => to_binary(&query_reward_streams(deps)?),
+        QueryMsg::GetTokenRewards { token_ids } => {
+            to_binary(&query_token_rewards(deps, env, token_ids)?)
+        }
+        QueryMsg::PendingRewards { owner } => to_binary(&query_pending_rewards(deps, env, owner)?),
+        QueryMsg::SimulateClaim { token_ids } => {
+            to_binary(&query_simulate_claim(deps, env, token_ids)?)
+        }
+        QueryMsg::GetRewardRemainders {} => to_binary(&query_reward_remainders(deps)?),
+        QueryMsg::GetTraitWeights { nft_address } => {
+            to_binary(&query_trait_weights(deps, nft_address)?)
+        }
+        QueryMsg::GetCollections {} => to_binary(&query_collections(deps)?),
+        QueryMsg::GetPendingOwner {} => to_binary(&query_pending_owner(deps)?),
+        QueryMsg::GetQueuedActions {} => to_binary(&query_queued_actions(deps)?),
+        QueryMsg::GetPauseState {} => to_binary(&query_pause_state(deps)?),
+        QueryMsg::Stats {} => to_binary(&query_stats(deps, env)?),
+        QueryMsg::Distributions {
+            stream_id,
+            start_after,
+            limit,
+        } => to_binary(&query_distributions(deps, stream_id, start_after, limit)?),
+        QueryMsg::UserHistory {
+            address,
+            start_after,
+            limit,
+        } => to_binary(&query_user_history(deps, address, start_after, limit)?),
+        QueryMsg::GetRoleHolders { role } => to_binary(&query_role_holders(deps, role)?),
+        QueryMsg::GetLockTiers {} => to_binary(&query_lock_tiers(deps)?),
+        QueryMsg::AllTokens { start_after, limit } => {
+            to_binary(&query_get_members(deps, start_after, limit)?)
+        }
+        QueryMsg::AllTokenInfos {
+            start_after,
+            limit,
+            status,
+        } => to_binary(&query_token_info(deps, env, start_after, limit, status)?),
+        QueryMsg::TokensByOwner {
+            owner,
+            start_after,
+            limit,
+        } => to_binary(&query_tokens_by_owner(
+            deps,
+            env,
+            owner,
+            start_after,
+            limit,
+        )?),
+    }
+}
+
+pub fn query_state_info(deps: Deps) -> StdResult<State> {
+    let state = CONFIG.load(deps.storage)?;
+    Ok(state)
+}
+
+pub fn query_pending_owner(deps: Deps) -> StdResult<Option<PendingOwner>> {
+    PENDING_OWNER.may_load(deps.storage)
+}
+
+pub fn query_stats(deps: Deps, env: Env) -> StdResult<StatsResponse> {
+    let mut state = CONFIG.load(deps.storage)?;
+    let now = env.block.time.seconds();
+    advance_state(deps.storage, &mut state, now)?;
+    let stats = load_stats(deps.storage)?;
+    let collections = COLLECTIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            item.map(|(address, collection)| CollectionStatsResponse {
+                address,
+                total_staked: collection.total_staked,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut recent = DISTRIBUTIONS
+        .prefix(0)
+        .range(deps.storage, None, None, Order::Descending)
+        .take(RECENT_DISTRIBUTIONS)
+        .map(|item| item.map(|(_id, distribution)| (distribution.time, distribution.amount)))
+        .collect::<StdResult<Vec<_>>>()?;
+    recent.reverse();
+
+    // Each distribution pays for the time since the one before it, so the oldest one in the
+    // window only marks where the covered period starts.
+    let distributed = match (recent.first(), recent.last()) {
+        (Some((from, _)), Some((to, _))) if to > from && !state.total_weight.is_zero() => {
+            let amount: Uint128 = recent[1..].iter().map(|(_, amount)| *amount).sum();
+            Some(amount.multiply_ratio(
+                SECONDS_PER_YEAR * DEFAULT_WEIGHT,
+                Uint128::from(to - from) * state.total_weight,
+            ))
+        }
+        _ => None,
+    };
+
+    // the emission pays its rate until it ends or the funded balance runs out
+    let from = now.max(state.emission_start);
+    let to = now.saturating_add(SECONDS_PER_YEAR).min(state.emission_end);
+    let emitted = if to > from && !state.total_weight.is_zero() {
+        let amount = state
+            .emission_rate
+            .saturating_mul(Uint128::from(to - from))
+            .min(state.emission_balance);
+        Some(amount.multiply_ratio(DEFAULT_WEIGHT, state.total_weight))
+    } else {
+        None
+    };
+
+    let annual_reward_per_nft = match (distributed, emitted) {
+        (None, None) => None,
+        (distributed, emitted) => {
+            Some(distributed.unwrap_or_default() + emitted.unwrap_or_default())
+        }
+    };
+
+    Ok(StatsResponse {
+        total_staked: state.total_staked,
+        collections,
+        unstaking_count: stats.unstaking_count,
+        last_distribution_amount: recent.last().map(|(_, amount)| *amount).unwrap_or_default(),
+        last_distribution_time: recent.last().map(|(time, _)| *time),
+        total_distributed: stats.total_distributed + stats.total_emission_funded
+            - state.emission_balance,
+        total_claimed: stats.total_claimed,
+        annual_reward_per_nft,
+    })
+}
+
+pub fn query_distributions(
+    deps: Deps,
+    stream_id: Option<u64>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<Distribution>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(id.joined_key()));
+    DISTRIBUTIONS
+        .prefix(stream_id.unwrap_or_default())
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_id, distribution)| distribution))
+        .collect()
+}
+
+pub fn query_user_history(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<Activity>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(id.joined_key()));
+    USER_HISTORY
+        .prefix(&address)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_id, activity)| activity))
+        .collect()
+}
+
+pub fn query_pause_state(deps: Deps) -> StdResult<PauseStateResponse> {
+    let state = CONFIG.load(deps.storage)?;
+    Ok(PauseStateResponse {
+        staking: !state.can_stake,
+        pause_state: load_pause_state(deps.storage)?,
+    })
+}
+
+pub fn query_queued_actions(deps: Deps) -> StdResult<Vec<QueuedAction>> {
+    QUEUED_ACTIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_id, queued_action)| queued_action))
+        .collect()
+}
+
+pub fn query_role_holders(deps: Deps, role: Role) -> StdResult<Vec<String>> {
+    ROLES
+        .prefix(role.as_str())
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect()
+}
+
+pub fn query_get_current_time(_deps: Deps, env: Env) -> StdResult<u64> {
+    Ok(env.block.time.seconds())
+}
+
+fn token_start_bound(start_after: Option<TokenKey>) -> Option<Bound> {
+    start_after
+        .map(|key| Bound::exclusive((key.nft_address.as_str(), key.token_id.as_str()).joined_key()))
+}
+
+pub fn query_get_members(
+    deps: Deps,
+    start_after: Option<TokenKey>,
+    limit: Option<u32>,
+) -> StdResult<Vec<TokenKey>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = token_start_bound(start_after);
+    tokens()
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|key| {
+            key.map(|(nft_address, token_id)| TokenKey {
+                nft_address,
+                token_id,
+            })
+        })
+        .collect()
+}
+
+pub fn query_token_info(
+    deps: Deps,
+    env: Env,
+    start_after: Option<TokenKey>,
+    limit: Option<u32>,
+    status: Option<String>,
+) -> StdResult<Vec<TokenInfo>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = token_start_bound(start_after);
+    let mut state = CONFIG.load(deps.storage)?;
+    let expiries = advance_state(deps.storage, &mut state, env.block.time.seconds())?;
+    let token_infos = match status {
+        Some(status) => {
+            tokens()
+                .idx
+                .status
+                .prefix(status)
+                .range(deps.storage, start, None, Order::Ascending)
+        }
+        None => tokens().range(deps.storage, start, None, Order::Ascending),
+    };
+    token_infos
+        .take(limit)
+        .map(|item| parse_token_info(&state, &expiries, item))
+        .collect()
+}
+
+fn parse_token_info(
+    state: &State,
+    expiries: &[LockExpiry],
+    item: StdResult<((String, String), TokenInfo)>,
+) -> StdResult<TokenInfo> {
+    item.map(|(_k, mut token_info)| {
+        apply_expiry(expiries, &mut token_info);
+        settle_reward(state, &mut token_info);
+        token_info
+    })
+}
+
+pub fn query_get_token(
+    deps: Deps,
+    env: Env,
+    nft_address: String,
+    token_id: String,
+) -> StdResult<TokenInfo> {
+    let mut state = CONFIG.load(deps.storage)?;
+    let expiries = advance_state(deps.storage, &mut state, env.block.time.seconds())?;
+    let mut token_info = tokens().load(deps.storage, (&nft_address, &token_id))?;
+    apply_expiry(&expiries, &mut token_info);
+    settle_reward(&state, &mut token_info);
+    Ok(token_info)
+}
+
+pub fn query_my_ids(deps: Deps, address: String) -> StdResult<Vec<TokenKey>> {
+    tokens()
+        .idx
+        .owner
+        .prefix(address)
+        .keys(deps.storage, None, None, Order::Ascending)
+        .map(|key| {
+            key.map(|(nft_address, token_id)| TokenKey {
+                nft_address,
+                token_id,
+            })
+        })
+        .collect()
+}
+
+pub fn query_my_info(deps: Deps, env: Env, address: String) -> StdResult<Vec<TokenInfo>> {
+    let mut state = CONFIG.load(deps.storage)?;
+    let expiries = advance_state(deps.storage, &mut state, env.block.time.seconds())?;
+    tokens()
+        .idx
+        .owner
+        .prefix(address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| parse_token_info(&state, &expiries, item))
+        .collect()
+}
+
+pub fn query_tokens_by_owner(
+    deps: Deps,
+    env: Env,
+    owner: String,
+    start_after: Option<TokenKey>,
+    limit: Option<u32>,
+) -> StdResult<Vec<TokenInfo>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = token_start_bound(start_after);
+    let mut state = CONFIG.load(deps.storage)?;
+    let expiries = advance_state(deps.storage, &mut state, env.block.time.seconds())?;
+    tokens()
+        .idx
+        .owner
+        .prefix(owner)
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| parse_token_info(&state, &expiries, item))
+        .collect()
+}
+
+pub fn query_collections(deps: Deps) -> StdResult<Vec<CollectionInfo>> {
+    COLLECTIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_address, collection)| collection))
+        .collect()
+}
+
+pub fn query_lock_tiers(deps: Deps) -> StdResult<Vec<LockTierResponse>> {
+    LOCK_TIERS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            item.map(|(duration, multiplier)| LockTierResponse {
+                duration,
+                multiplier,
+            })
+        })
+        .collect()
+}
+
+pub fn query_reward_streams(deps: Deps) -> StdResult<Vec<RewardStream>> {
+    load_reward_streams(deps.storage)
+}
+
+pub fn query_reward_remainders(deps: Deps) -> StdResult<Vec<StreamRewardResponse>> {
+    let state = CONFIG.load(deps.storage)?;
+    let mut res = vec![StreamRewardResponse {
+        stream_id: 0,
+        asset: RewardAsset::Cw20 {
+            address: state.token_address,
+        },
+        amount: state.reward_remainder / Uint128::new(REWARD_SCALE),
+    }];
+    for stream in load_reward_streams(deps.storage)? {
+        res.push(StreamRewardResponse {
+            stream_id: stream.id,
+            asset: stream.asset,
+            amount: stream.reward_remainder / Uint128::new(REWARD_SCALE),
+        });
+    }
+    Ok(res)
+}
+
+pub fn query_trait_weights(deps: Deps, nft_address: String) -> StdResult<Vec<TraitWeightResponse>> {
+    TRAIT_WEIGHTS
+        .sub_prefix(&nft_address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            item.map(|((trait_type, value), weight)| TraitWeightResponse {
+                trait_type,
+                value,
+                weight,
+            })
+        })
+        .collect()
+}
+
+pub fn query_token_rewards(
+    deps: Deps,
+    env: Env,
+    token_ids: Vec<TokenKey>,
+) -> StdResult<Vec<TokenRewardsResponse>> {
+    let mut state = CONFIG.load(deps.storage)?;
+    let expiries = advance_state(deps.storage, &mut state, env.block.time.seconds())?;
+    let streams = load_reward_streams(deps.storage)?;
+
+    token_ids
+        .into_iter()
+        .map(|key| {
+            let token_info = tokens().load(deps.storage, (&key.nft_address, &key.token_id))?;
+            token_rewards(deps.storage, &state, &expiries, &streams, token_info)
+        })
+        .collect()
+}
+
+pub fn query_pending_rewards(
+    deps: Deps,
+    env: Env,
+    owner: String,
+) -> StdResult<ClaimSimulationResponse> {
+    let token_infos = tokens()
+        .idx
+        .owner
+        .prefix(owner)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_key, token_info)| token_info))
+        .collect::<StdResult<Vec<_>>>()?;
+    simulate_claim(deps, env, token_infos)
+}
+
+pub fn query_simulate_claim(
+    deps: Deps,
+    env: Env,
+    token_ids: Vec<TokenKey>,
+) -> StdResult<ClaimSimulationResponse> {
+    let mut token_infos: Vec<TokenInfo> = vec![];
+    for (index, key) in token_ids.iter().enumerate() {
+        // refused like in `GetReward`
+        if token_ids[..index].contains(key) {
+            return Err(StdError::generic_err("Token listed more than once"));
+        }
+        token_infos.push(tokens().load(deps.storage, (&key.nft_address, &key.token_id))?);
+    }
+    simulate_claim(deps, env, token_infos)
+}
+
+fn simulate_claim(
+    deps: Deps,
+    env: Env,
+    token_infos: Vec<TokenInfo>,
+) -> StdResult<ClaimSimulationResponse> {
+    let mut state = CONFIG.load(deps.storage)?;
+    let expiries = advance_state(deps.storage, &mut state, env.block.time.seconds())?;
+    let streams = load_reward_streams(deps.storage)?;
+
+    let mut total = vec![StreamRewardResponse {
+        stream_id: 0,
+        asset: RewardAsset::Cw20 {
+            address: state.token_address.clone(),
+        },
+        amount: Uint128::new(0),
+    }];
+    total.extend(streams.iter().map(|stream| StreamRewardResponse {
+        stream_id: stream.id,
+        asset: stream.asset.clone(),
+        amount: Uint128::new(0),
+    }));
+
+    let tokens = token_infos
+        .into_iter()
+        .map(|token_info| token_rewards(deps.storage, &state, &expiries, &streams, token_info))
+        .collect::<StdResult<Vec<_>>>()?;
+    for token in &tokens {
+        for (total, reward) in total.iter_mut().zip(&token.rewards) {
+            total.amount += reward.amount;
+        }
+    }
+
+    Ok(ClaimSimulationResponse { total, tokens })
+}
+
+/// Pending amount of `token_info` in every stream, settled against the current accumulators.
+fn token_rewards(
+    storage: &dyn Storage,
+    state: &State,
+    expiries: &[LockExpiry],
+    streams: &[RewardStream],
+    mut token_info: TokenInfo,
+) -> StdResult<TokenRewardsResponse> {
+    // streams are settled while the token still has the weight it held since its checkpoints
+    let stream_rewards = settle_stream_rewards(storage, streams, &token_info)?;
+    apply_expiry(expiries, &mut token_info);
+    settle_reward(state, &mut token_info);
+
+    let mut rewards = vec![StreamRewardResponse {
+        stream_id: 0,
+        asset: RewardAsset::Cw20 {
+            address: state.token_address.clone(),
+        },
+        amount: token_info.reward,
+    }];
+    for (stream, stream_reward) in streams.iter().zip(stream_rewards) {
+        rewards.push(StreamRewardResponse {
+            stream_id: stream.id,
+            asset: stream.asset.clone(),
+            amount: stream_reward.reward,
+        });
+    }
+
+    Ok(TokenRewardsResponse {
+        nft_address: token_info.nft_address,
+        token_id: token_info.token_id,
+        rewards,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::state::{LegacyState, LegacyTokenInfo, Trait};
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockStorage, MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        from_slice, ContractResult, CosmosMsg, Empty, OwnedDeps, Querier, QuerierResult,
+        QueryRequest, SystemResult, WasmQuery,
+    };
+    use cw721::{NftInfoResponse, OwnerOfResponse};
+    use std::marker::PhantomData;
+
+    #[test]
+    fn testing() {
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+            denom: "ujuno".to_string(),
+            staking_period: 1000,
+            reward_wallet: "reward_wallet".to_string(),
+            distribute_period: 100,
+            token_address: "token_address".to_string(),
+            nft_address: vec!["nft_address".to_string()],
+            claim_reward: Uint128::new(500),
+        };
+        let info = mock_info("creator", &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(
+            state,
+            State {
+                nft_address: vec!["nft_address".to_string()],
+                token_address: "token_address".to_string(),
+                owner: "creator".to_string(),
+                staking_period: 1000,
+                denom: "ujuno".to_string(),
+                reward_wallet: "reward_wallet".to_string(),
+                total_staked: Uint128::new(0),
+                can_stake: true,
+                last_distribute: mock_env().block.time.seconds(),
+                distribute_period: 100,
+                claim_reward: Uint128::new(500),
+                reward_per_token: Uint128::new(0),
+                emission_rate: Uint128::new(0),
+                emission_start: 0,
+                emission_end: 0,
+                emission_balance: Uint128::new(0),
+                last_emission: mock_env().block.time.seconds(),
+                reward_remainder: Uint128::new(0),
+                total_weight: Uint128::new(0),
+                emergency_penalty: 0,
+                treasury: None,
+                timelock_delay: 0
+            }
+        );
+
+        println!("{:?}", "add nft address");
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddNftAddress {
+            address: "nft_address1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        println!("{:?}", "add token address");
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetTokenAddress {
+            address: "token_address1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(
+            state.nft_address,
+            vec!["nft_address".to_string(), "nft_address1".to_string()]
+        );
+        assert_eq!(state.token_address, "token_address1".to_string());
+
+        println!("{:?}", "set reward wallet");
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetRewardWallet {
+            address: "reward_wallet1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        println!("{:?}", "set distribute period");
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetDistributePeriod { time: 150 };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.distribute_period, 150);
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.reward_wallet, "reward_wallet1".to_string());
+
+        println!("{:?}", "set staking period");
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetStakingPeriod { time: 1200 };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.staking_period, 1200);
+
+        let info = mock_info("nft_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: "reveal1".to_string(),
+            msg: to_binary(&"abc".to_string()).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("nft_address1", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: "reveal2".to_string(),
+            msg: to_binary(&"abc".to_string()).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // let my_nfs = query_my_ids(deps,"")
+
+        let tokens = query_get_members(deps.as_ref(), None, None).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                token_key("nft_address", "reveal1"),
+                token_key("nft_address1", "reveal2")
+            ]
+        );
+
+        let my_ids = query_my_ids(deps.as_ref(), "owner2".to_string()).unwrap();
+        let eq_my_ids: Vec<TokenKey> = vec![];
+        assert_eq!(my_ids, eq_my_ids);
+
+        println!("{:?}", "tokens of owner1");
+
+        let my_ids = query_my_ids(deps.as_ref(), "owner1".to_string()).unwrap();
+        assert_eq!(
+            my_ids,
+            vec![
+                token_key("nft_address", "reveal1"),
+                token_key("nft_address1", "reveal2")
+            ]
+        );
+
+        println!("{:?}", "tokens of owner2");
+
+        let my_token_infos =
+            query_my_info(deps.as_ref(), mock_env(), "owner2".to_string()).unwrap();
+        let eq_my_ids: Vec<TokenInfo> = vec![];
+        assert_eq!(my_token_infos, eq_my_ids);
+
+        println!("{:?}", "token informations of owner1");
+
+        let my_token_infos =
+            query_my_info(deps.as_ref(), mock_env(), "owner1".to_string()).unwrap();
+        assert_eq!(
+            my_token_infos,
+            vec![
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal1".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Staked".to_string(),
+                    reward: Uint128::new(0),
+                    reward_checkpoint: Uint128::new(0),
+                    unstake_time: 0,
+                    nft_address: "nft_address".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                },
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal2".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Staked".to_string(),
+                    reward: Uint128::new(0),
+                    reward_checkpoint: Uint128::new(0),
+                    unstake_time: 0,
+                    nft_address: "nft_address1".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                }
+            ]
+        );
+
+        println!("{:?}", "unstake reveal1");
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "reveal1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+
+        assert_eq!(state.total_staked, Uint128::new(1));
+
+        let tokens = query_get_members(deps.as_ref(), None, None).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                token_key("nft_address", "reveal1"),
+                token_key("nft_address1", "reveal2")
+            ]
+        );
+
+        let token_infos = query_token_info(deps.as_ref(), mock_env(), None, None, None).unwrap();
+        assert_eq!(
+            token_infos,
+            vec![
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal1".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Unstaking".to_string(),
+                    reward: Uint128::new(0),
+                    reward_checkpoint: Uint128::new(0),
+                    unstake_time: mock_env().block.time.seconds(),
+                    nft_address: "nft_address".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                },
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal2".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Staked".to_string(),
+                    reward: Uint128::new(0),
+                    reward_checkpoint: Uint128::new(0),
+                    unstake_time: 0,
+                    nft_address: "nft_address1".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                }
+            ]
+        );
+
+        let my_token_infos =
+            query_my_info(deps.as_ref(), mock_env(), "owner1".to_string()).unwrap();
+        assert_eq!(
+            my_token_infos,
+            vec![
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal1".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Unstaking".to_string(),
+                    reward: Uint128::new(0),
+                    reward_checkpoint: Uint128::new(0),
+                    unstake_time: mock_env().block.time.seconds(),
+                    nft_address: "nft_address".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                },
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal2".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Staked".to_string(),
+                    reward: Uint128::new(0),
+                    reward_checkpoint: Uint128::new(0),
+                    unstake_time: 0,
+                    nft_address: "nft_address1".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                }
+            ]
+        );
+
+        let info = mock_info("token_address1", &[]);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "reward_wallet1".to_string(),
+            amount: Uint128::new(10),
+            msg: to_binary(&ReceiveMsg::DistributeReward {}).unwrap(),
+        });
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(150);
+        execute(deps.as_mut(), env, info, msg).unwrap();
+
+        println!("{:?}", "check the reward distribution");
+
+        let token_infos = query_token_info(deps.as_ref(), mock_env(), None, None, None).unwrap();
+        assert_eq!(
+            token_infos,
+            vec![
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal1".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Unstaking".to_string(),
+                    reward: Uint128::new(0),
+                    reward_checkpoint: Uint128::new(100_000_000_000),
+                    unstake_time: mock_env().block.time.seconds(),
+                    nft_address: "nft_address".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                },
+                TokenInfo {
+                    owner: "owner1".to_string(),
+                    token_id: "reveal2".to_string(),
+                    stake_time: mock_env().block.time.seconds(),
+                    status: "Staked".to_string(),
+                    reward: Uint128::new(10),
+                    reward_checkpoint: Uint128::new(100_000_000_000),
+                    unstake_time: 0,
+                    nft_address: "nft_address1".to_string(),
+                    weight: 100,
+                    base_weight: 100,
+                    lock_end: 0
+                }
+            ]
+        );
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::GetReward {
+            token_ids: vec![
+                token_key("nft_address", "reveal1"),
+                token_key("nft_address1", "reveal2"),
+            ],
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(1, res.messages.len());
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_address1".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "owner1".to_string(),
+                    amount: Uint128::new(10)
+                })
+                .unwrap(),
+                funds: vec![]
+            })
+        );
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "reveal1".to_string(),
+        };
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(1200);
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+
+        let my_ids = query_my_ids(deps.as_ref(), "owner1".to_string()).unwrap();
+        assert_eq!(my_ids, vec![token_key("nft_address1", "reveal2")]);
+
+        assert_eq!(1, res.messages.len());
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "nft_address".to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: "owner1".to_string(),
+                    token_id: "reveal1".to_string()
+                })
+                .unwrap(),
+                funds: vec![]
+            })
+        );
+
+        let tokens = query_get_members(deps.as_ref(), None, None).unwrap();
+        assert_eq!(tokens, vec![token_key("nft_address1", "reveal2")]);
+
+        let id_info = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address1".to_string(),
+            "reveal2".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            id_info,
+            TokenInfo {
+                owner: "owner1".to_string(),
+                token_id: "reveal2".to_string(),
+                stake_time: mock_env().block.time.seconds(),
+                status: "Staked".to_string(),
+                reward: Uint128::new(0),
+                reward_checkpoint: Uint128::new(100_000_000_000),
+                unstake_time: 0,
+                nft_address: "nft_address1".to_string(),
+                weight: 100,
+                base_weight: 100,
+                lock_end: 0
+            }
+        );
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::Migrate {
+            amount: Uint128::new(10),
+            address: "new_staking".to_string(),
+            id: vec![
+                token_key("nft_address", "reveal1"),
+                token_key("nft_address1", "reveal2"),
+            ],
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "nft_address1".to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: "new_staking".to_string(),
+                    token_id: "reveal2".to_string()
+                })
+                .unwrap(),
+                funds: vec![]
+            })
+        );
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_address1".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "new_staking".to_string(),
+                    amount: Uint128::new(10)
+                })
+                .unwrap(),
+                funds: vec![]
+            })
+        );
+    }
+
+    fn setup_contract(deps: DepsMut) {
+        let instantiate_msg = InstantiateMsg {
+            denom: "ujuno".to_string(),
+            staking_period: 1000,
+            reward_wallet: "reward_wallet".to_string(),
+            distribute_period: 100,
+            token_address: "token_address".to_string(),
+            nft_address: vec!["nft_address".to_string()],
+            claim_reward: Uint128::new(500),
+        };
+        let info = mock_info("creator", &[]);
+        instantiate(deps, mock_env(), info, instantiate_msg).unwrap();
+    }
+
+    fn token_key(nft_address: &str, token_id: &str) -> TokenKey {
+        TokenKey {
+            nft_address: nft_address.to_string(),
+            token_id: token_id.to_string(),
+        }
+    }
+
+    fn env_after(seconds: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(seconds);
+        env
+    }
+
+    fn stake_nft(deps: DepsMut, env: Env, nft_address: &str, owner: &str, token_id: &str) {
+        let info = mock_info(nft_address, &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: owner.to_string(),
+            token_id: token_id.to_string(),
+            msg: Binary::default(),
+        });
+        execute(deps, env, info, msg).unwrap();
+    }
+
+    fn distribute_msg(sender: &str, amount: u128) -> ExecuteMsg {
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: sender.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&ReceiveMsg::DistributeReward {}).unwrap(),
+        })
+    }
+
+    fn distribute(deps: DepsMut, env: Env, amount: u128) {
+        let info = mock_info("token_address", &[]);
+        execute(deps, env, info, distribute_msg("reward_wallet", amount)).unwrap();
+    }
+
+    #[test]
+    fn distribution_accrues_lazily() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "2");
+        distribute(deps.as_mut(), env_after(100), 100);
+
+        // distributing only moves the accumulator, stored tokens are not rewritten
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.reward_per_token, Uint128::new(500_000_000_000));
+        let stored = tokens().load(&deps.storage, ("nft_address", "1")).unwrap();
+        assert_eq!(stored.reward, Uint128::new(0));
+        assert_eq!(stored.reward_checkpoint, Uint128::new(0));
+
+        // a token staked after a distribution does not share in it
+        stake_nft(deps.as_mut(), env_after(150), "nft_address", "owner3", "3");
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address".to_string(),
+            "3".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(0));
+
+        // an unstaking token keeps what it had earned but stops accruing
+        let info = mock_info("owner2", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "2".to_string(),
+        };
+        execute(deps.as_mut(), env_after(150), info, msg).unwrap();
+        distribute(deps.as_mut(), env_after(200), 60);
+
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address".to_string(),
+            "1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(80));
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address".to_string(),
+            "2".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(50));
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address".to_string(),
+            "3".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(30));
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::GetReward {
+            token_ids: vec![token_key("nft_address", "1")],
+        };
+        let res = execute(deps.as_mut(), env_after(200), info, msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_address".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "owner1".to_string(),
+                    amount: Uint128::new(80)
+                })
+                .unwrap(),
+                funds: vec![]
+            })
+        );
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address".to_string(),
+            "1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(0));
+
+        // an amount the scaled accumulator cannot hold is refused instead of panicking
+        let info = mock_info("token_address", &[]);
+        let msg = distribute_msg("reward_wallet", u128::MAX / REWARD_SCALE + 1);
+        let err = execute(deps.as_mut(), env_after(300), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::Overflow { .. })));
+    }
+
+    #[test]
+    fn token_ids_are_scoped_by_collection() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddNftAddress {
+            address: "nft_address1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address1", "owner2", "1");
+
+        let info = mock_info("nft_address1", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner2".to_string(),
+            token_id: "1".to_string(),
+            msg: Binary::default(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::AlreadyStaked {}));
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_staked, Uint128::new(2));
+
+        // owner1 cannot touch the token of the same id living in the other collection
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address1".to_string(),
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address1".to_string(),
+            "1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.owner, "owner2".to_string());
+        let my_ids = query_my_ids(deps.as_ref(), "owner2".to_string()).unwrap();
+        assert_eq!(my_ids, vec![token_key("nft_address1", "1")]);
+    }
+
+    #[test]
+    fn migrate_rekeys_legacy_tokens() {
+        let mut deps = mock_dependencies();
+        let legacy_state = LegacyState {
+            owner: "creator".to_string(),
+            denom: "ujuno".to_string(),
+            staking_period: 1000,
+            reward_wallet: "reward_wallet".to_string(),
+            total_staked: Uint128::new(1),
+            nft_address: vec!["nft_address".to_string(), "nft_address1".to_string()],
+            token_address: "token_address".to_string(),
+            can_stake: true,
+            last_distribute: 0,
+            distribute_period: 100,
+            claim_reward: Uint128::new(500),
+        };
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "0.10.0").unwrap();
+        LEGACY_CONFIG
+            .save(&mut deps.storage, &legacy_state)
+            .unwrap();
+        for (nft_address, token_id, status) in [
+            ("nft_address", "1", "Staked"),
+            ("nft_address1", "2", "Unstaking"),
+        ] {
+            let legacy_token = LegacyTokenInfo {
+                owner: "owner1".to_string(),
+                token_id: token_id.to_string(),
+                status: status.to_string(),
+                unstake_time: 0,
+                stake_time: 0,
+                reward: Uint128::new(7),
+                nft_address: nft_address.to_string(),
+            };
+            LEGACY_TOKENINFO
+                .save(&mut deps.storage, token_id, &legacy_token)
+                .unwrap();
+        }
+        // "3" was withdrawn long ago but the old layout kept it in the owned list
+        LEGACY_OWNEDTOKEN
+            .save(
+                &mut deps.storage,
+                "owner1",
+                &vec!["1".to_string(), "2".to_string(), "3".to_string()],
+            )
+            .unwrap();
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_staked, Uint128::new(1));
+        assert_eq!(state.reward_per_token, Uint128::new(0));
+        let staked: Vec<u64> = query_collections(deps.as_ref())
+            .unwrap()
+            .into_iter()
+            .map(|collection| collection.total_staked)
+            .collect();
+        assert_eq!(staked, vec![1, 0]);
+
+        let my_ids = query_my_ids(deps.as_ref(), "owner1".to_string()).unwrap();
+        assert_eq!(
+            my_ids,
+            vec![
+                token_key("nft_address", "1"),
+                token_key("nft_address1", "2")
+            ]
+        );
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address1".to_string(),
+            "2".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.status, "Unstaking".to_string());
+        assert_eq!(token.reward, Uint128::new(7));
+        assert_eq!(token.weight, DEFAULT_WEIGHT);
+        assert!(!LEGACY_TOKENINFO.has(&deps.storage, "1"));
+        assert!(!LEGACY_OWNEDTOKEN.has(&deps.storage, "owner1"));
+        assert_eq!(
+            query_stats(deps.as_ref(), mock_env())
+                .unwrap()
+                .unstaking_count,
+            1
+        );
+
+        let version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(version.version, CONTRACT_VERSION.to_string());
+
+        // migrating to the same version leaves the migrated data alone
+        distribute(deps.as_mut(), env_after(100), 10);
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.reward_per_token, Uint128::new(100_000_000_000));
+    }
+
+    #[test]
+    fn distribution_requires_received_tokens() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+
+        // a Receive hook from any contract other than the reward token is not backed by funds
+        let info = mock_info("other_token", &[]);
+        let msg = distribute_msg("reward_wallet", 100);
+        let err = execute(deps.as_mut(), env_after(100), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::WrongTokenContract {}));
+
+        // the reward wallet calling directly cannot pose as the token contract either
+        let info = mock_info("reward_wallet", &[]);
+        let msg = distribute_msg("reward_wallet", 100);
+        let err = execute(deps.as_mut(), env_after(100), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::WrongTokenContract {}));
+
+        // tokens sent by anyone but the reward wallet are not accepted as a distribution
+        let info = mock_info("token_address", &[]);
+        let msg = distribute_msg("owner1", 100);
+        let err = execute(deps.as_mut(), env_after(100), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        distribute(deps.as_mut(), env_after(100), 100);
+        let token = query_get_token(
+            deps.as_ref(),
+            mock_env(),
+            "nft_address".to_string(),
+            "1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(100));
+    }
+
+    #[test]
+    fn migrate_rejects_unknown_versions() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        set_contract_version(&mut deps.storage, "crates.io:cw721-base", "0.10.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMigration { .. }));
+
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "0.9.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMigration { .. }));
+
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMigration { .. }));
+    }
+
+    #[test]
+    fn emission_streams_to_stakers() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let start = mock_env().block.time.seconds();
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::SetRewardEmission {
+            rate: Uint128::new(10),
+            start_time: start + 100,
+            end_time: start + 200,
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetRewardEmission {
+            rate: Uint128::new(10),
+            start_time: start + 200,
+            end_time: start + 100,
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidEmission {}));
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetRewardEmission {
+            rate: Uint128::new(10),
+            start_time: start + 100,
+            end_time: start + 200,
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("token_address", &[]);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "reward_wallet".to_string(),
+            amount: Uint128::new(900),
+            msg: to_binary(&ReceiveMsg::FundEmission {}).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), env_after(150), "nft_address", "owner2", "2");
+
+        // nothing is emitted before the start, then 10 per second is shared by the stakers
+        let token = query_get_token(
+            deps.as_ref(),
+            env_after(150),
+            "nft_address".to_string(),
+            "1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(500));
+        let token = query_get_token(
+            deps.as_ref(),
+            env_after(170),
+            "nft_address".to_string(),
+            "1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(600));
+        let token = query_get_token(
+            deps.as_ref(),
+            env_after(170),
+            "nft_address".to_string(),
+            "2".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(100));
+        // the emitted 700 counts as distributed
+        assert_eq!(
+            query_stats(deps.as_ref(), env_after(170))
+                .unwrap()
+                .total_distributed,
+            Uint128::new(700)
+        );
+        // 400 is left to emit over two tokens, short of the 500 the rate would pay until the end
+        assert_eq!(
+            query_stats(deps.as_ref(), env_after(150))
+                .unwrap()
+                .annual_reward_per_nft,
+            Some(Uint128::new(200))
+        );
+
+        // unstaking stops the accrual of that token only
+        let info = mock_info("owner2", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "2".to_string(),
+        };
+        execute(deps.as_mut(), env_after(170), info, msg).unwrap();
+
+        // the funded 900 runs out 20 seconds before the configured end
+        let token = query_get_token(
+            deps.as_ref(),
+            env_after(500),
+            "nft_address".to_string(),
+            "1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(800));
+        let token = query_get_token(
+            deps.as_ref(),
+            env_after(500),
+            "nft_address".to_string(),
+            "2".to_string(),
+        )
+        .unwrap();
+        assert_eq!(token.reward, Uint128::new(100));
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::GetReward {
+            token_ids: vec![token_key("nft_address", "1")],
+        };
+        execute(deps.as_mut(), env_after(500), info, msg).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.emission_balance, Uint128::new(0));
+        assert_eq!(state.last_emission, start + 500);
+    }
+
+    fn stream_distribute_msg(sender: &str, amount: u128, stream_id: u64) -> ExecuteMsg {
+        ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: sender.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&ReceiveMsg::DistributeStreamReward { stream_id }).unwrap(),
+        })
+    }
+
+    #[test]
+    fn reward_streams_pay_out_together() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddRewardStream {
+            asset: RewardAsset::Cw20 {
+                address: "partner_token".to_string(),
+            },
+            funder: "partner".to_string(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+        assert_eq!(res.attributes[0].value, "1".to_string());
+
+        // every stream is settled on each token operation, so only the owner adds them
+        let info = mock_info("creator", &[]);
+        let grant_msg = ExecuteMsg::GrantRole {
+            role: Role::Distributor,
+            address: "distributor".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, grant_msg).unwrap();
+        let info = mock_info("distributor", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+
+        // only the stream token contract reports funds, and only the funder may send them
+        let info = mock_info("token_address", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stream_distribute_msg("partner", 40, 1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::WrongTokenContract {}));
+        let info = mock_info("partner_token", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stream_distribute_msg("owner1", 40, 1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let info = mock_info("partner_token", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stream_distribute_msg("partner", 40, 1),
+        )
+        .unwrap();
+
+        // a token staked after the partner distribution does not share in it
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "2");
+        let info = mock_info("partner_token", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stream_distribute_msg("partner", 40, 1),
+        )
+        .unwrap();
+        distribute(deps.as_mut(), env_after(100), 10);
+
+        let rewards = query_token_rewards(
+            deps.as_ref(),
+            mock_env(),
+            vec![token_key("nft_address", "1"), token_key("nft_address", "2")],
+        )
+        .unwrap();
+        assert_eq!(
+            rewards[0].rewards,
+            vec![
+                StreamRewardResponse {
+                    stream_id: 0,
+                    asset: RewardAsset::Cw20 {
+                        address: "token_address".to_string()
+                    },
+                    amount: Uint128::new(5)
+                },
+                StreamRewardResponse {
+                    stream_id: 1,
+                    asset: RewardAsset::Cw20 {
+                        address: "partner_token".to_string()
+                    },
+                    amount: Uint128::new(60)
+                }
+            ]
+        );
+        assert_eq!(rewards[1].rewards[1].amount, Uint128::new(20));
+
+        // each stream lists its own distributions, sharing ids with the primary one
+        let distributions = query_distributions(deps.as_ref(), Some(1), None, None).unwrap();
+        assert_eq!(
+            distributions
+                .iter()
+                .map(|d| (d.id, d.stream_id, d.total_staked, d.share))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, 1, Uint128::new(1), Uint128::new(40)),
+                (2, 1, Uint128::new(2), Uint128::new(20))
+            ]
+        );
+        let distributions = query_distributions(deps.as_ref(), None, None, None).unwrap();
+        assert_eq!(distributions.len(), 1);
+        assert_eq!(distributions[0].id, 3);
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::GetReward {
+            token_ids: vec![token_key("nft_address", "1"), token_key("nft_address", "2")],
+        };
+        let res = execute(deps.as_mut(), env_after(100), info, msg).unwrap();
+        assert_eq!(
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>(),
+            vec![
+                cw20_transfer("token_address", "owner1", Uint128::new(10)).unwrap(),
+                cw20_transfer("partner_token", "owner1", Uint128::new(80)).unwrap()
+            ]
+        );
+
+        let rewards = query_token_rewards(
+            deps.as_ref(),
+            mock_env(),
+            vec![token_key("nft_address", "1")],
+        )
+        .unwrap();
+        assert_eq!(rewards[0].rewards[1].amount, Uint128::new(0));
+    }
+
+    #[test]
+    fn native_stream_pays_with_bank_sends() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddRewardStream {
+            asset: RewardAsset::Native {
+                denom: "uatom".to_string(),
+            },
+            funder: "partner".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::WrongDenom { .. }));
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddRewardStream {
+            asset: RewardAsset::Native {
+                denom: "ujuno".to_string(),
+            },
+            funder: "partner".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+
+        let msg = ExecuteMsg::DistributeNativeReward { stream_id: 1 };
+        let info = mock_info("partner", &coins(30, "uatom"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Notenough {}));
+        let info = mock_info("partner", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Notenough {}));
+        let info = mock_info("owner1", &coins(30, "ujuno"));
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // native streams cannot be funded with CW20 tokens
+        let info = mock_info("token_address", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stream_distribute_msg("partner", 30, 1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::WrongTokenContract {}));
+
+        let info = mock_info("partner", &coins(30, "ujuno"));
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let distributions = query_distributions(deps.as_ref(), Some(1), None, None).unwrap();
+        assert_eq!(distributions.len(), 1);
+        assert_eq!(distributions[0].amount, Uint128::new(30));
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let res = execute(deps.as_mut(), env_after(1000), info, msg).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "owner1".to_string(),
+                amount: coins(30, "ujuno")
+            })
+        );
+        assert!(!STREAM_REWARDS.has(&deps.storage, (1, "nft_address", "1")));
+
+        let msg = ExecuteMsg::AddRewardStream {
+            asset: RewardAsset::Native {
+                denom: "ujuno".to_string(),
+            },
+            funder: "partner".to_string(),
+        };
+        for _ in 1..MAX_REWARD_STREAMS {
+            let info = mock_info("creator", &[]);
+            execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap();
+        }
+        let info = mock_info("creator", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::TooManyRewardStreams {
+                max: MAX_REWARD_STREAMS
+            }
+        ));
+    }
+
+    #[test]
+    fn distribution_remainder_is_carried() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddRewardStream {
+            asset: RewardAsset::Cw20 {
+                address: "partner_token".to_string(),
+            },
+            funder: "partner".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        for token_id in ["1", "2", "3"] {
+            stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", token_id);
+        }
+
+        distribute(deps.as_mut(), env_after(100), 10);
+        let info = mock_info("partner_token", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stream_distribute_msg("partner", 5, 1),
+        )
+        .unwrap();
+
+        // the shares are scaled, so nothing is held back, but a token is only credited whole units
+        let remainders = query_reward_remainders(deps.as_ref()).unwrap();
+        assert_eq!(remainders[0].amount, Uint128::new(0));
+        assert_eq!(remainders[1].amount, Uint128::new(0));
+        let rewards = query_token_rewards(
+            deps.as_ref(),
+            mock_env(),
+            vec![token_key("nft_address", "1")],
+        )
+        .unwrap();
+        assert_eq!(rewards[0].rewards[0].amount, Uint128::new(3));
+        assert_eq!(rewards[0].rewards[1].amount, Uint128::new(1));
+
+        // the fractions left over from 10 and 11 add up to whole units, same for 5 and 7
+        distribute(deps.as_mut(), env_after(200), 11);
+        let info = mock_info("partner_token", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stream_distribute_msg("partner", 7, 1),
+        )
+        .unwrap();
+
+        let remainders = query_reward_remainders(deps.as_ref()).unwrap();
+        assert_eq!(remainders[0].amount, Uint128::new(0));
+        assert_eq!(remainders[1].amount, Uint128::new(0));
+
+        let rewards = query_token_rewards(
+            deps.as_ref(),
+            mock_env(),
+            vec![
+                token_key("nft_address", "1"),
+                token_key("nft_address", "2"),
+                token_key("nft_address", "3"),
+            ],
+        )
+        .unwrap();
+        let credited: Vec<Uint128> = (0..2)
+            .map(|stream| {
+                rewards
+                    .iter()
+                    .map(|token| token.rewards[stream].amount)
+                    .sum()
+            })
+            .collect();
+        assert_eq!(credited, vec![Uint128::new(21), Uint128::new(12)]);
+    }
+
+    /// Answers `AllNftInfo` with the traits listed for each token id.
+    struct TraitQuerier {
+        traits: Vec<(&'static str, &'static str)>,
+    }
+
+    impl Querier for TraitQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            let token_id = match from_slice(bin_request).unwrap() {
+                QueryRequest::<Empty>::Wasm(WasmQuery::Smart { msg, .. }) => {
+                    match from_binary(&msg).unwrap() {
+                        Cw721QueryMsg::AllNftInfo { token_id, .. } => token_id,
+                        _ => panic!("unexpected cw721 query"),
+                    }
+                }
+                _ => panic!("unexpected query"),
+            };
+            let attributes = self
+                .traits
+                .iter()
+                .filter(|(id, _)| *id == token_id)
+                .map(|(_, rarity)| Trait {
+                    trait_type: "rarity".to_string(),
+                    value: rarity.to_string(),
+                })
+                .collect();
+            let res = AllNftInfoResponse {
+                access: OwnerOfResponse {
+                    owner: MOCK_CONTRACT_ADDR.to_string(),
+                    approvals: vec![],
+                },
+                info: NftInfoResponse {
+                    token_uri: None,
+                    extension: Some(Metadata {
+                        image: None,
+                        attributes: Some(attributes),
+                    }),
+                },
+            };
+            SystemResult::Ok(ContractResult::Ok(to_binary(&res).unwrap()))
+        }
+    }
+
+    fn trait_weight_msg(value: &str, weight: Option<u64>) -> ExecuteMsg {
+        ExecuteMsg::SetTraitWeight {
+            nft_address: "nft_address".to_string(),
+            trait_type: "rarity".to_string(),
+            value: value.to_string(),
+            weight,
+        }
+    }
+
+    #[test]
+    fn rarity_weights_split_distributions() {
+        let mut deps = OwnedDeps {
+            storage: MockStorage::default(),
+            api: MockApi::default(),
+            querier: TraitQuerier {
+                traits: vec![("1", "legendary"), ("2", "common"), ("3", "legendary")],
+            },
+            custom_query_type: PhantomData::<Empty>,
+        };
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("owner1", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            trait_weight_msg("legendary", Some(300)),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("creator", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            trait_weight_msg("legendary", Some(0)),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidWeight { .. }));
+        let info = mock_info("creator", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            trait_weight_msg("legendary", Some(MAX_WEIGHT + 1)),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidWeight { max: MAX_WEIGHT }
+        ));
+
+        let info = mock_info("creator", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            trait_weight_msg("legendary", Some(300)),
+        )
+        .unwrap();
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetTokenWeight {
+            nft_address: "nft_address".to_string(),
+            token_id: "3".to_string(),
+            weight: Some(200),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        for token_id in ["1", "2", "3"] {
+            stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", token_id);
+        }
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_weight, Uint128::new(600));
+
+        // weights changed after staking only apply to tokens staked afterwards
+        let info = mock_info("creator", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            trait_weight_msg("legendary", Some(500)),
+        )
+        .unwrap();
+        assert_eq!(
+            query_trait_weights(deps.as_ref(), "nft_address".to_string()).unwrap(),
+            vec![TraitWeightResponse {
+                trait_type: "rarity".to_string(),
+                value: "legendary".to_string(),
+                weight: 500
+            }]
+        );
+
+        distribute(deps.as_mut(), env_after(100), 6000);
+        let rewards: Vec<(u64, Uint128)> =
+            query_my_info(deps.as_ref(), mock_env(), "owner1".to_string())
+                .unwrap()
+                .into_iter()
+                .map(|token| (token.weight, token.reward))
+                .collect();
+        assert_eq!(
+            rewards,
+            vec![
+                (300, Uint128::new(3000)),
+                (100, Uint128::new(1000)),
+                (200, Uint128::new(2000))
+            ]
+        );
+    }
+
+    #[test]
+    fn collection_parameters_apply_per_collection() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddNftAddress {
+            address: "nft_address1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = ExecuteMsg::SetCollectionConfig {
+            address: "nft_address1".to_string(),
+            reward_weight: 200,
+            enabled: true,
+            max_staked: Some(1),
+        };
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetUnbondingPeriod {
+            address: "nft_address1".to_string(),
+            unbonding_period: Some(50),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetCollectionConfig {
+            address: "nft_address".to_string(),
+            reward_weight: 100,
+            enabled: false,
+            max_staked: None,
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("nft_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: "1".to_string(),
+            msg: Binary::default(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::CollectionDisabled {}));
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address1", "owner1", "1");
+        let info = mock_info("nft_address1", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: "2".to_string(),
+            msg: Binary::default(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::CollectionFull {}));
+
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_weight, Uint128::new(200));
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address1".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let collections = query_collections(deps.as_ref()).unwrap();
+        assert_eq!(collections[1].total_staked, 0);
+
+        // the collection's own unbonding period applies instead of `staking_period`
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address1".to_string(),
+            token_id: "1".to_string(),
+        };
+        let err = execute(deps.as_mut(), env_after(49), info.clone(), msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::TimeRemaining {}));
+        execute(deps.as_mut(), env_after(50), info, msg).unwrap();
+    }
+
+    #[test]
+    fn collections_can_be_disabled_and_removed() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            denom: "ujuno".to_string(),
+            staking_period: 1000,
+            reward_wallet: "reward_wallet".to_string(),
+            distribute_period: 100,
+            token_address: "token_address".to_string(),
+            nft_address: vec!["nft_address".to_string(), "nft_address".to_string()],
+            claim_reward: Uint128::new(500),
+        };
+        let err =
+            instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::DuplicateNftContract {}));
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::AddNftAddress {
+            address: "nft_address".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::DuplicateNftContract {}));
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::DisableNftAddress {
+            address: "nft_address".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("nft_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: "2".to_string(),
+            msg: Binary::default(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::CollectionDisabled {}));
+
+        // stakers of a disabled collection can still leave
+        let info = mock_info("owner1", &[]);
+        let unstake_msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, unstake_msg).unwrap();
+
+        let remove_msg = ExecuteMsg::RemoveNftAddress {
+            address: "nft_address".to_string(),
+        };
+        let info = mock_info("creator", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, remove_msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::CollectionInUse {}));
+
+        let info = mock_info("owner1", &[]);
+        let withdraw_msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), env_after(1000), info, withdraw_msg).unwrap();
+
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), mock_env(), info, remove_msg).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert!(state.nft_address.is_empty());
+        assert!(query_collections(deps.as_ref()).unwrap().is_empty());
+
+        let info = mock_info("nft_address", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::WrongNftContract {}));
+    }
+
+    fn stake_locked_msg(token_id: &str, lock_duration: u64) -> ExecuteMsg {
+        ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: token_id.to_string(),
+            msg: to_binary(&StakeMsg {
+                lock_duration: Some(lock_duration),
+            })
+            .unwrap(),
+        })
+    }
+
+    #[test]
+    fn lock_tiers_multiply_rewards_until_unlocked() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let month = 30 * 24 * 60 * 60;
+
+        let msg = ExecuteMsg::SetLockTier {
+            duration: month,
+            multiplier: Some(200),
+        };
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetLockTier {
+            duration: u64::MAX,
+            multiplier: Some(MAX_WEIGHT + 1),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidWeight { max: MAX_WEIGHT }
+        ));
+        assert_eq!(
+            query_lock_tiers(deps.as_ref()).unwrap(),
+            vec![LockTierResponse {
+                duration: month,
+                multiplier: 200
+            }]
+        );
+
+        let info = mock_info("nft_address", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stake_locked_msg("1", month + 1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidLockTier {}));
+
+        // a lock request that does not parse is refused rather than staked unlocked
+        let info = mock_info("nft_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: "1".to_string(),
+            msg: Binary::from(format!(r#"{{"lock_duration":"{}"}}"#, month).as_bytes()),
+        });
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidLockTier {}));
+
+        let info = mock_info("nft_address", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stake_locked_msg("1", month),
+        )
+        .unwrap();
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "2");
+
+        distribute(deps.as_mut(), env_after(100), 3000);
+        let rewards: Vec<Uint128> = query_my_info(deps.as_ref(), mock_env(), "owner1".to_string())
+            .unwrap()
+            .into_iter()
+            .map(|token| token.reward)
+            .collect();
+        assert_eq!(rewards, vec![Uint128::new(2000), Uint128::new(1000)]);
+
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), env_after(100), info, msg.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::Locked { lock_end } if lock_end == mock_env().block.time.seconds() + month
+        ));
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(month), info, msg).unwrap();
+
+        // a lock ending past the last representable second is refused, not wrapped
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetLockTier {
+            duration: u64::MAX,
+            multiplier: Some(300),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("nft_address", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            stake_locked_msg("3", u64::MAX),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidLockTier {}));
+    }
+
+    #[test]
+    fn lock_weight_drops_after_lock_end() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let month = 30 * 24 * 60 * 60;
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetLockTier {
+            duration: month,
+            multiplier: Some(200),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        for token_id in ["1", "2"] {
+            let info = mock_info("nft_address", &[]);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                info,
+                stake_locked_msg(token_id, month),
+            )
+            .unwrap();
+        }
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "3");
+        distribute(deps.as_mut(), env_after(100), 5000);
+
+        // queries already see the locks as over, before anything is written
+        let weights: Vec<u64> =
+            query_my_info(deps.as_ref(), env_after(month), "owner1".to_string())
+                .unwrap()
+                .iter()
+                .map(|token| token.weight)
+                .collect();
+        assert_eq!(weights, vec![100, 100, 100]);
+
+        // nobody touches the locked tokens, yet the next distribution is split at base weight
+        distribute(deps.as_mut(), env_after(month), 3000);
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_weight, Uint128::new(300));
+        let token_infos =
+            query_my_info(deps.as_ref(), env_after(month), "owner1".to_string()).unwrap();
+        assert_eq!(
+            token_infos
+                .iter()
+                .map(|token| (token.weight, token.reward))
+                .collect::<Vec<_>>(),
+            vec![
+                (100, Uint128::new(3000)),
+                (100, Uint128::new(3000)),
+                (100, Uint128::new(2000))
+            ]
+        );
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "2".to_string(),
+        };
+        execute(deps.as_mut(), env_after(month), info, msg).unwrap();
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::CancelUnstake {
+            nft_address: "nft_address".to_string(),
+            token_id: "2".to_string(),
+        };
+        execute(deps.as_mut(), env_after(month), info, msg).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_weight, Uint128::new(300));
+    }
+
+    #[test]
+    fn emission_switches_weight_at_lock_end() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let start = mock_env().block.time.seconds();
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetLockTier {
+            duration: 100,
+            multiplier: Some(200),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetRewardEmission {
+            rate: Uint128::new(30),
+            start_time: start,
+            end_time: start + 200,
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("token_address", &[]);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "reward_wallet".to_string(),
+            amount: Uint128::new(6000),
+            msg: to_binary(&ReceiveMsg::FundEmission {}).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("nft_address", &[]);
+        execute(deps.as_mut(), mock_env(), info, stake_locked_msg("1", 100)).unwrap();
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "2");
+
+        // 3000 split 2:1 while locked, then 3000 split evenly
+        let rewards: Vec<Uint128> =
+            query_my_info(deps.as_ref(), env_after(200), "owner1".to_string())
+                .unwrap()
+                .iter()
+                .map(|token| token.reward)
+                .collect();
+        assert_eq!(rewards, vec![Uint128::new(3500), Uint128::new(2500)]);
+    }
+
+    #[test]
+    fn staked_weight_multiplies_before_dividing() {
+        // dividing after each factor would give 3 * 50 / 100 * 300 / 100 = 3
+        assert_eq!(staked_weight(3, 50, 300).unwrap(), 4);
+        assert_eq!(staked_weight(1, 1, 1).unwrap(), 1);
+        assert_eq!(
+            staked_weight(MAX_WEIGHT, MAX_WEIGHT, MAX_WEIGHT).unwrap(),
+            100_000_000
+        );
+    }
+
+    #[test]
+    fn batch_unstake_and_withdraw_are_atomic() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for token_id in ["1", "2", "3"] {
+            stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", token_id);
+        }
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "4");
+        distribute(deps.as_mut(), env_after(100), 4000);
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNfts {
+            token_ids: vec![token_key("nft_address", "1"), token_key("nft_address", "4")],
+        };
+        let err = execute(deps.as_mut(), env_after(100), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNfts {
+            token_ids: vec![token_key("nft_address", "1"), token_key("nft_address", "1")],
+        };
+        let err = execute(deps.as_mut(), env_after(100), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::DuplicateToken {}));
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNfts {
+            token_ids: vec![token_key("nft_address", "1"), token_key("nft_address", "2")],
+        };
+        execute(deps.as_mut(), env_after(100), info, msg).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_staked, Uint128::new(2));
+        assert_eq!(state.total_weight, Uint128::new(200));
+
+        // "3" is still staked, so neither of the others is withdrawn
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::WithdrawNfts {
+            token_ids: vec![
+                token_key("nft_address", "1"),
+                token_key("nft_address", "2"),
+                token_key("nft_address", "3"),
+            ],
+        };
+        let err = execute(deps.as_mut(), env_after(1100), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::StatusError {}));
+        assert!(tokens()
+            .may_load(&deps.storage, ("nft_address", "1"))
+            .unwrap()
+            .is_some());
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::WithdrawNfts {
+            token_ids: vec![token_key("nft_address", "1"), token_key("nft_address", "2")],
+        };
+        let res = execute(deps.as_mut(), env_after(1100), info, msg).unwrap();
+        let nft_transfer = |token_id: &str| {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "nft_address".to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: "owner1".to_string(),
+                    token_id: token_id.to_string(),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        };
+        assert_eq!(
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>(),
+            vec![
+                nft_transfer("1"),
+                nft_transfer("2"),
+                cw20_transfer("token_address", "owner1", Uint128::new(2000)).unwrap()
+            ]
+        );
+        let my_ids = query_my_ids(deps.as_ref(), "owner1".to_string()).unwrap();
+        assert_eq!(my_ids, vec![token_key("nft_address", "3")]);
+    }
+
+    #[test]
+    fn cancel_unstake_resumes_accrual() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "2");
+        distribute(deps.as_mut(), env_after(100), 2000);
+
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), env_after(100), info, msg).unwrap();
+        distribute(deps.as_mut(), env_after(200), 1000);
+
+        let cancel_msg = |token_id: &str| ExecuteMsg::CancelUnstake {
+            nft_address: "nft_address".to_string(),
+            token_id: token_id.to_string(),
+        };
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), env_after(200), info, cancel_msg("2")).unwrap_err();
+        assert!(matches!(err, ContractError::StatusError {}));
+        let info = mock_info("owner2", &[]);
+        let err = execute(deps.as_mut(), env_after(200), info, cancel_msg("1")).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(200), info, cancel_msg("1")).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_staked, Uint128::new(2));
+        assert_eq!(query_collections(deps.as_ref()).unwrap()[0].total_staked, 2);
+
+        // nothing distributed while it was unstaking reaches the token
+        distribute(deps.as_mut(), env_after(300), 2000);
+        let tokens: Vec<(String, u64, Uint128)> =
+            query_my_info(deps.as_ref(), mock_env(), "owner1".to_string())
+                .unwrap()
+                .into_iter()
+                .map(|token| (token.status, token.unstake_time, token.reward))
+                .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                ("Staked".to_string(), 0, Uint128::new(2000)),
+                ("Staked".to_string(), 0, Uint128::new(3000))
+            ]
+        );
+    }
+
+    #[test]
+    fn pause_switches_are_independent() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "2");
+        distribute(deps.as_mut(), env_after(100), 2000);
+
+        let pause = |deps: DepsMut, switch: PauseSwitch, paused: bool| {
+            let info = mock_info("creator", &[]);
+            execute(
+                deps,
+                mock_env(),
+                info,
+                ExecuteMsg::SetPaused { switch, paused },
+            )
+            .unwrap();
+        };
+        let unstake_msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let withdraw_msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let claim_msg = ExecuteMsg::GetReward {
+            token_ids: vec![token_key("nft_address", "2")],
+        };
+
+        pause(deps.as_mut(), PauseSwitch::Unstaking, true);
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), env_after(100), info, unstake_msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::UnstakingPaused {}));
+        pause(deps.as_mut(), PauseSwitch::Unstaking, false);
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(100), info, unstake_msg).unwrap();
+
+        // withdraw and claim stay open while only staking is paused
+        pause(deps.as_mut(), PauseSwitch::Staking, true);
+        let info = mock_info("nft_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: "owner1".to_string(),
+            token_id: "3".to_string(),
+            msg: Binary::default(),
+        });
+        let err = execute(deps.as_mut(), env_after(100), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::CanNotStake {}));
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(100), info, claim_msg.clone()).unwrap();
+
+        pause(deps.as_mut(), PauseSwitch::Withdrawing, true);
+        pause(deps.as_mut(), PauseSwitch::Claiming, true);
+        pause(deps.as_mut(), PauseSwitch::Distributing, true);
+        assert_eq!(
+            query_pause_state(deps.as_ref()).unwrap(),
+            PauseStateResponse {
+                staking: true,
+                pause_state: PauseState {
+                    unstaking: false,
+                    withdrawing: true,
+                    claiming: true,
+                    distributing: true,
+                },
+            }
+        );
+
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), env_after(1100), info, withdraw_msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::WithdrawingPaused {}));
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), env_after(1100), info, claim_msg).unwrap_err();
+        assert!(matches!(err, ContractError::ClaimingPaused {}));
+        let info = mock_info("token_address", &[]);
+        let msg = distribute_msg("reward_wallet", 1000);
+        let err = execute(deps.as_mut(), env_after(200), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::DistributingPaused {}));
+
+        pause(deps.as_mut(), PauseSwitch::Withdrawing, false);
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(1100), info, withdraw_msg).unwrap();
+    }
+
+    #[test]
+    fn token_queries_are_paginated() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for token_id in 0..35 {
+            let token_id = format!("{:02}", token_id);
+            stake_nft(
+                deps.as_mut(),
+                mock_env(),
+                "nft_address",
+                "owner1",
+                &token_id,
+            );
+        }
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "03".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let tokens = query_get_members(deps.as_ref(), None, None).unwrap();
+        assert_eq!(tokens.len(), 10);
+        assert_eq!(tokens[9], token_key("nft_address", "09"));
+        let tokens = query_get_members(deps.as_ref(), None, Some(100)).unwrap();
+        assert_eq!(tokens.len(), 30);
+        let start_after = Some(token_key("nft_address", "29"));
+        let tokens = query_get_members(deps.as_ref(), start_after, Some(100)).unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[0], token_key("nft_address", "30"));
+
+        let status = Some("Unstaking".to_string());
+        let token_infos =
+            query_token_info(deps.as_ref(), mock_env(), None, None, status.clone()).unwrap();
+        assert_eq!(token_infos.len(), 1);
+        assert_eq!(token_infos[0].token_id, "03");
+        let start_after = Some(token_key("nft_address", "03"));
+        let token_infos =
+            query_token_info(deps.as_ref(), mock_env(), start_after, None, status).unwrap();
+        assert!(token_infos.is_empty());
+    }
+
+    #[test]
+    fn owner_index_follows_stakes() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "2");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "3");
+
+        let token_ids = |deps: Deps, owner: &str, start_after: Option<TokenKey>| {
+            query_tokens_by_owner(deps, mock_env(), owner.to_string(), start_after, Some(1))
+                .unwrap()
+                .into_iter()
+                .map(|token_info| token_info.token_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(token_ids(deps.as_ref(), "owner1", None), vec!["1"]);
+        let start_after = Some(token_key("nft_address", "1"));
+        assert_eq!(token_ids(deps.as_ref(), "owner1", start_after), vec!["3"]);
+        let start_after = Some(token_key("nft_address", "3"));
+        assert!(token_ids(deps.as_ref(), "owner1", start_after).is_empty());
+
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner1", &[]), msg).unwrap();
+        let msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(1000), info, msg).unwrap();
+        assert_eq!(
+            query_my_ids(deps.as_ref(), "owner1".to_string()).unwrap(),
+            vec![token_key("nft_address", "3")]
+        );
+        assert_eq!(
+            query_my_ids(deps.as_ref(), "owner2".to_string()).unwrap(),
+            vec![token_key("nft_address", "2")]
+        );
+    }
+
+    #[test]
+    fn claim_simulation_matches_the_transfer() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "2");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "3");
+        distribute(deps.as_mut(), env_after(100), 3000);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), env_after(100), mock_info("owner1", &[]), msg).unwrap();
+        distribute(deps.as_mut(), env_after(200), 2000);
+
+        let pending =
+            query_pending_rewards(deps.as_ref(), mock_env(), "owner1".to_string()).unwrap();
+        let amounts: Vec<Uint128> = pending
+            .tokens
+            .iter()
+            .map(|token| token.rewards[0].amount)
+            .collect();
+        assert_eq!(amounts, vec![Uint128::new(1000), Uint128::new(2000)]);
+        assert_eq!(pending.total[0].amount, Uint128::new(3000));
+
+        // repeated ids are refused
+        let token_ids = vec![token_key("nft_address", "3"), token_key("nft_address", "3")];
+        query_simulate_claim(deps.as_ref(), mock_env(), token_ids.clone()).unwrap_err();
+        let msg = ExecuteMsg::GetReward { token_ids };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner1", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::DuplicateToken {}));
+
+        let token_ids = vec![token_key("nft_address", "3")];
+        let simulated = query_simulate_claim(deps.as_ref(), mock_env(), token_ids.clone()).unwrap();
+        assert_eq!(simulated.total[0].amount, Uint128::new(2000));
+
+        let msg = ExecuteMsg::GetReward { token_ids };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner1", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            cw20_transfer("token_address", "owner1", simulated.total[0].amount).unwrap()
+        );
+
+        let token_ids = vec![token_key("nft_address", "1")];
+        let simulated = query_simulate_claim(deps.as_ref(), env_after(1100), token_ids).unwrap();
+        assert_eq!(simulated.total[0].amount, Uint128::new(1000));
+        let msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            env_after(1100),
+            mock_info("owner1", &[]),
+            msg,
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages[1].msg,
+            cw20_transfer("token_address", "owner1", simulated.total[0].amount).unwrap()
+        );
+    }
+
+    #[test]
+    fn stats_track_distributions_and_claims() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "2");
+
+        let stats = query_stats(deps.as_ref(), mock_env()).unwrap();
+        assert_eq!(stats.last_distribution_time, None);
+        assert_eq!(stats.annual_reward_per_nft, None);
+
+        distribute(deps.as_mut(), env_after(100), 1000);
+        assert_eq!(
+            query_stats(deps.as_ref(), mock_env())
+                .unwrap()
+                .annual_reward_per_nft,
+            None
+        );
+        distribute(deps.as_mut(), env_after(200), 2000);
+
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), env_after(200), mock_info("owner1", &[]), msg).unwrap();
+        let msg = ExecuteMsg::GetReward {
+            token_ids: vec![token_key("nft_address", "2")],
+        };
+        execute(deps.as_mut(), env_after(200), mock_info("owner2", &[]), msg).unwrap();
+
+        // 2000 over 100 seconds, now shared by a single token
+        assert_eq!(
+            query_stats(deps.as_ref(), mock_env()).unwrap(),
+            StatsResponse {
+                total_staked: Uint128::new(1),
+                collections: vec![CollectionStatsResponse {
+                    address: "nft_address".to_string(),
+                    total_staked: 1,
+                }],
+                unstaking_count: 1,
+                last_distribution_amount: Uint128::new(2000),
+                last_distribution_time: Some(mock_env().block.time.seconds() + 200),
+                total_distributed: Uint128::new(3000),
+                total_claimed: Uint128::new(1500),
+                annual_reward_per_nft: Some(Uint128::new(20 * SECONDS_PER_YEAR as u128)),
+            }
+        );
+
+        let msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            env_after(1200),
+            mock_info("owner1", &[]),
+            msg,
+        )
+        .unwrap();
+        let stats = query_stats(deps.as_ref(), mock_env()).unwrap();
+        assert_eq!(stats.unstaking_count, 0);
+        assert_eq!(stats.total_claimed, Uint128::new(3000));
+    }
+
+    #[test]
+    fn distributions_are_recorded() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "2");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "3");
+        for i in 1..=12 {
+            distribute(deps.as_mut(), env_after(100 * i), 1000);
+        }
+
+        let distributions = query_distributions(deps.as_ref(), None, None, None).unwrap();
+        assert_eq!(distributions.len(), 10);
+        // the share is rounded down for display, the fraction is still credited to the tokens
+        assert_eq!(
+            distributions[0],
+            Distribution {
+                id: 1,
+                stream_id: 0,
+                time: mock_env().block.time.seconds() + 100,
+                amount: Uint128::new(1000),
+                total_staked: Uint128::new(3),
+                share: Uint128::new(333),
+            }
+        );
+        assert_eq!(distributions[1].share, Uint128::new(333));
+
+        let distributions = query_distributions(deps.as_ref(), None, Some(10), Some(100)).unwrap();
+        let ids: Vec<u64> = distributions.iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![11, 12]);
+    }
+
+    #[test]
+    fn user_history_logs_every_action() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "2");
+        distribute(deps.as_mut(), env_after(100), 2000);
+
+        let unstake_msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(100), info, unstake_msg.clone()).unwrap();
+        let msg = ExecuteMsg::CancelUnstake {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), env_after(200), mock_info("owner1", &[]), msg).unwrap();
+        let msg = ExecuteMsg::GetReward {
+            token_ids: vec![token_key("nft_address", "1")],
+        };
+        execute(deps.as_mut(), env_after(300), mock_info("owner1", &[]), msg).unwrap();
+        distribute(deps.as_mut(), env_after(400), 2000);
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(400), info, unstake_msg).unwrap();
+        let msg = ExecuteMsg::WithdrawNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(
+            deps.as_mut(),
+            env_after(1400),
+            mock_info("owner1", &[]),
+            msg,
+        )
+        .unwrap();
+
+        let now = mock_env().block.time.seconds();
+        let history: Vec<(u64, ActivityKind, u64, Uint128)> =
+            query_user_history(deps.as_ref(), "owner1".to_string(), None, None)
+                .unwrap()
+                .into_iter()
+                .map(|activity| (activity.id, activity.kind, activity.time, activity.amount))
+                .collect();
+        assert_eq!(
+            history,
+            vec![
+                (1, ActivityKind::Stake, now, Uint128::new(0)),
+                (2, ActivityKind::Unstake, now + 100, Uint128::new(0)),
+                (3, ActivityKind::CancelUnstake, now + 200, Uint128::new(0)),
+                (4, ActivityKind::Claim, now + 300, Uint128::new(1000)),
+                (5, ActivityKind::Unstake, now + 400, Uint128::new(0)),
+                (6, ActivityKind::Withdraw, now + 1400, Uint128::new(1000)),
+            ]
+        );
+
+        let history =
+            query_user_history(deps.as_ref(), "owner1".to_string(), Some(4), Some(1)).unwrap();
+        assert_eq!(history[0].kind, ActivityKind::Unstake);
+        let history = query_user_history(deps.as_ref(), "owner2".to_string(), None, None).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].token_id, "2");
+    }
+
+    #[test]
+    fn emergency_withdraw_forfeits_penalty() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let month = 30 * 24 * 60 * 60;
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetEmergencyPenalty {
+            percent: 101,
+            treasury: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPenalty {}));
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetEmergencyPenalty {
+            percent: 25,
+            treasury: None,
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner1", "1");
+        stake_nft(deps.as_mut(), mock_env(), "nft_address", "owner2", "2");
+        distribute(deps.as_mut(), env_after(100), 2000);
+
+        // a staked token can leave, and its forfeit goes to the remaining staker
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::EmergencyWithdraw {
+            nft_address: "nft_address".to_string(),
+            token_id: "1".to_string(),
+        };
+        let res = execute(deps.as_mut(), env_after(100), info, msg).unwrap();
+        assert_eq!(
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>(),
+            vec![
+                nft_transfer("nft_address", "owner1", "1").unwrap(),
+                cw20_transfer("token_address", "owner1", Uint128::new(750)).unwrap()
+            ]
+        );
+        assert!(query_my_ids(deps.as_ref(), "owner1".to_string())
+            .unwrap()
+            .is_empty());
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.total_staked, Uint128::new(1));
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetEmergencyPenalty {
+            percent: 25,
+            treasury: Some("treasury".to_string()),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let info = mock_info("owner2", &[]);
+        let msg = ExecuteMsg::UnstakeNft {
+            nft_address: "nft_address".to_string(),
+            token_id: "2".to_string(),
+        };
+        execute(deps.as_mut(), env_after(100), info, msg).unwrap();
+        // "2" holds its 1000 plus the 250 forfeited by "1", a quarter of it goes to the treasury
+        let info = mock_info("owner2", &[]);
+        let msg = ExecuteMsg::EmergencyWithdraw {
+            nft_address: "nft_address".to_string(),
+            token_id: "2".to_string(),
+        };
+        let res = execute(deps.as_mut(), env_after(100), info, msg).unwrap();
+        assert_eq!(
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>(),
+            vec![
+                nft_transfer("nft_address", "owner2", "2").unwrap(),
+                cw20_transfer("token_address", "owner2", Uint128::new(938)).unwrap(),
+                cw20_transfer("token_address", "treasury", Uint128::new(312)).unwrap()
+            ]
+        );
+
+        // claiming first would leave nothing to forfeit, so a locked token cannot leave early
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetLockTier {
+            duration: month,
+            multiplier: Some(200),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("nft_address", &[]);
+        execute(
+            deps.as_mut(),
+            env_after(100),
+            info,
+            stake_locked_msg("3", month),
+        )
+        .unwrap();
+        distribute(deps.as_mut(), env_after(200), 1000);
+        let info = mock_info("owner1", &[]);
+        let msg = ExecuteMsg::GetReward {
+            token_ids: vec![token_key("nft_address", "3")],
+        };
+        let res = execute(deps.as_mut(), env_after(200), info, msg).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            cw20_transfer("token_address", "owner1", Uint128::new(1000)).unwrap()
+        );
+        let msg = ExecuteMsg::EmergencyWithdraw {
+            nft_address: "nft_address".to_string(),
+            token_id: "3".to_string(),
+        };
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), env_after(200), info, msg.clone()).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::Locked { lock_end } if lock_end == mock_env().block.time.seconds() + 100 + month
+        ));
+        let info = mock_info("owner1", &[]);
+        execute(deps.as_mut(), env_after(100 + month), info, msg).unwrap();
+    }
+
+    #[test]
+    fn ownership_transfer_takes_two_steps() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let now = mock_env().block.time.seconds();
+
+        let propose_msg = |expires_at: Option<u64>| ExecuteMsg::ProposeOwner {
+            address: "new_owner".to_string(),
+            expires_at,
+        };
+        let info = mock_info("new_owner", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, propose_msg(None)).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let info = mock_info("creator", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            propose_msg(Some(now + 100)),
+        )
+        .unwrap();
+        assert_eq!(
+            query_pending_owner(deps.as_ref()).unwrap(),
+            Some(PendingOwner {
+                address: "new_owner".to_string(),
+                expires_at: Some(now + 100)
+            })
+        );
+
+        let info = mock_info("owner1", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::AcceptOwnership {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("new_owner", &[]);
+        let err = execute(
+            deps.as_mut(),
+            env_after(100),
+            info,
+            ExecuteMsg::AcceptOwnership {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::OwnershipExpired {}));
+
+        let info = mock_info("new_owner", &[]);
+        let msg = ExecuteMsg::CancelOwnershipTransfer {};
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("new_owner", &[]);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::AcceptOwnership {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NoPendingOwner {}));
+
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), mock_env(), info, propose_msg(None)).unwrap();
+        let info = mock_info("new_owner", &[]);
+        execute(
+            deps.as_mut(),
+            env_after(100),
+            info,
+            ExecuteMsg::AcceptOwnership {},
+        )
+        .unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.owner, "new_owner".to_string());
+        assert_eq!(query_pending_owner(deps.as_ref()).unwrap(), None);
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetTokenAddress {
+            address: "token_address1".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+    }
+
+    #[test]
+    fn roles_gate_operational_settings() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let pause_msg = ExecuteMsg::SetStake { flag: false };
+        let grant_msg = ExecuteMsg::GrantRole {
+            role: Role::Pauser,
+            address: "pauser".to_string(),
+        };
+        let info = mock_info("pauser", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, pause_msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("pauser", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, grant_msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), mock_env(), info, grant_msg).unwrap();
+        assert_eq!(
+            query_role_holders(deps.as_ref(), Role::Pauser).unwrap(),
+            vec!["pauser".to_string()]
+        );
+        assert!(query_role_holders(deps.as_ref(), Role::Treasurer)
+            .unwrap()
+            .is_empty());
+
+        let info = mock_info("pauser", &[]);
+        execute(deps.as_mut(), mock_env(), info, pause_msg.clone()).unwrap();
+        assert!(!query_state_info(deps.as_ref()).unwrap().can_stake);
+
+        // a role only opens its own settings
+        let info = mock_info("pauser", &[]);
+        let msg = ExecuteMsg::SetRewardWallet {
+            address: "reward_wallet1".to_string(),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::RevokeRole {
+            role: Role::Pauser,
+            address: "pauser".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("pauser", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, pause_msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // the owner keeps every role
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), mock_env(), info, pause_msg).unwrap();
+    }
+
+    #[test]
+    fn timelocked_actions_wait_for_the_delay() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let now = mock_env().block.time.seconds();
+
+        // a delay that would keep queued changes out of reach is refused
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetTimelockDelay { delay: u64::MAX };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidTimelockDelay {
+                max: MAX_TIMELOCK_DELAY
+            }
+        ));
+
+        // without a delay the change applies at once
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetTimelockDelay { delay: 1000 };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            query_state_info(deps.as_ref()).unwrap().timelock_delay,
+            1000
+        );
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetTokenAddress {
+            address: "token_address1".to_string(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(res.attributes[0].value, "1".to_string());
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.token_address, "token_address".to_string());
+        assert_eq!(
+            query_queued_actions(deps.as_ref()).unwrap(),
+            vec![QueuedAction {
+                id: 1,
+                action: TimelockedAction::SetTokenAddress {
+                    address: "token_address1".to_string()
+                },
+                eta: now + 1000
+            }]
+        );
+
+        let msg = ExecuteMsg::ExecuteQueuedAction { id: 1 };
+        let info = mock_info("owner1", &[]);
+        let err = execute(deps.as_mut(), env_after(1000), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let info = mock_info("creator", &[]);
+        let err = execute(deps.as_mut(), env_after(999), info, msg.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::TimeRemaining {}));
+        let info = mock_info("creator", &[]);
+        execute(deps.as_mut(), env_after(1000), info, msg.clone()).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.token_address, "token_address1".to_string());
+        let info = mock_info("creator", &[]);
+        let err = execute(deps.as_mut(), env_after(1000), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::NoQueuedAction { id: 1 }));
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetRewardWallet {
+            address: "reward_wallet1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::CancelQueuedAction { id: 2 };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert!(query_queued_actions(deps.as_ref()).unwrap().is_empty());
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.reward_wallet, "reward_wallet".to_string());
+
+        // stakers get the same warning before their unbonding period or penalty changes
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetUnbondingPeriod {
+            address: "nft_address2".to_string(),
+            unbonding_period: Some(50),
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::WrongNftContract {}));
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetUnbondingPeriod {
+            address: "nft_address".to_string(),
+            unbonding_period: Some(50),
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetEmergencyPenalty {
+            percent: 101,
+            treasury: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPenalty {}));
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::SetEmergencyPenalty {
+            percent: 25,
+            treasury: None,
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            query_collections(deps.as_ref()).unwrap()[0].unbonding_period,
+            None
+        );
+        assert_eq!(
+            query_state_info(deps.as_ref()).unwrap().emergency_penalty,
+            0
+        );
+
+        for id in [3, 4] {
+            let info = mock_info("creator", &[]);
+            let msg = ExecuteMsg::ExecuteQueuedAction { id };
+            execute(deps.as_mut(), env_after(1000), info, msg).unwrap();
+        }
+        assert_eq!(
+            query_collections(deps.as_ref()).unwrap()[0].unbonding_period,
+            Some(50)
+        );
+        assert_eq!(
+            query_state_info(deps.as_ref()).unwrap().emergency_penalty,
+            25
+        );
     }
 }
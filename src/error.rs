@@ -6,35 +6,108 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("Semver parsing error: {0}")]
+    SemVer(String),
+
     #[error("Unauthorized")]
     Unauthorized {},
 
+    #[error("No ownership transfer is pending")]
+    NoPendingOwner {},
+
+    #[error("Ownership offer expired")]
+    OwnershipExpired {},
+
     #[error("Wrong nft contract error")]
     WrongNftContract {},
 
-    
+    #[error("Wrong token contract error")]
+    WrongTokenContract {},
+
     #[error("Not enough funds")]
-    Notenough{},
+    Notenough {},
+
+    #[error("Only {denom} can be used for native rewards")]
+    WrongDenom { denom: String },
+
+    #[error("Alreay staked")]
+    AlreadyStaked {},
+
+    #[error("Not staked")]
+    NotStaked {},
 
-     #[error("Alreay staked")]
-    AlreadyStaked{},
+    #[error("Token listed more than once")]
+    DuplicateToken {},
 
-     #[error("Not staked")]
-    NotStaked{},
- 
     #[error("Time remaining yet")]
     TimeRemaining {},
 
-    
+    #[error("No queued action {id}")]
+    NoQueuedAction { id: u64 },
+
+    #[error("Locked until {lock_end}")]
+    Locked { lock_end: u64 },
+
+    #[error("Unknown lock duration")]
+    InvalidLockTier {},
+
+    #[error("Penalty can not exceed 100 percent")]
+    InvalidPenalty {},
+
     #[error("Can not stake")]
     CanNotStake {},
 
+    #[error("Unstaking is paused")]
+    UnstakingPaused {},
+
+    #[error("Withdrawing is paused")]
+    WithdrawingPaused {},
+
+    #[error("Claiming is paused")]
+    ClaimingPaused {},
+
+    #[error("Distributing is paused")]
+    DistributingPaused {},
+
+    #[error("Collection is disabled")]
+    CollectionDisabled {},
+
+    #[error("Collection is already allowed")]
+    DuplicateNftContract {},
+
+    #[error("Collection still holds staked tokens")]
+    CollectionInUse {},
+
+    #[error("Collection staking limit reached")]
+    CollectionFull {},
+
     #[error("Can not distribute")]
     CanNotDistribute {},
 
+    #[error("Emission must end after it starts")]
+    InvalidEmission {},
+
+    #[error("Weight must be between 1 and {max}")]
+    InvalidWeight { max: u64 },
+
+    #[error("No more than {max} reward streams can be added")]
+    TooManyRewardStreams { max: u64 },
+
+    #[error("Timelock delay can not exceed {max} seconds")]
+    InvalidTimelockDelay { max: u64 },
+
     #[error("There are no nfts for claim")]
     NoNftClaim {},
-    
+
     #[error("Stkaing process")]
     StatusError {},
+
+    #[error("Can not migrate from {contract} {version}")]
+    InvalidMigration { contract: String, version: String },
+}
+
+impl From<semver::Error> for ContractError {
+    fn from(err: semver::Error) -> Self {
+        Self::SemVer(err.to_string())
+    }
 }
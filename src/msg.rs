@@ -1,48 +1,388 @@
-use cosmwasm_std::{ Uint128};
+use cosmwasm_std::Uint128;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use cw20::Cw20ReceiveMsg;
 use cw721::Cw721ReceiveMsg;
 
-
+use crate::state::{PauseState, RewardAsset, Role, TokenKey};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
-    pub denom:String,
-    pub staking_period : u64,
-    pub reward_wallet : String,
+    pub denom: String,
+    pub staking_period: u64,
+    pub reward_wallet: String,
     pub distribute_period: u64,
-    pub nft_address:Vec<String>,
-    pub token_address:String,
-    pub claim_reward:Uint128
+    pub nft_address: Vec<String>,
+    pub token_address: String,
+    pub claim_reward: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     ReceiveNft(Cw721ReceiveMsg),
-    UnstakeNft{token_id:String},
-    WithdrawNft{token_id:String},
-    GetReward{token_ids:Vec<String>},  
-    DistributeReward{token_amount:Uint128},
-    SetRewardWallet{address:String},
-    SetOwner{address:String},
-    SetStakingPeriod{time:u64},   
-    SetTokenAddress{address:String},
-    SetStake{flag:bool},
-    SetDistributePeriod{time:u64},
-    Migrate{amount:Uint128,address:String,id:Vec<String>},
-    SetClaimAmount{amount:Uint128},
-    AddNftAddress{address:String}
+    UnstakeNft {
+        nft_address: String,
+        token_id: String,
+    },
+    WithdrawNft {
+        nft_address: String,
+        token_id: String,
+    },
+    /// Same as `UnstakeNft` for several tokens at once. Nothing is unstaked unless every token
+    /// can be.
+    UnstakeNfts {
+        token_ids: Vec<TokenKey>,
+    },
+    /// Same as `WithdrawNft` for several tokens at once, paying their rewards together. Nothing
+    /// is withdrawn unless every token can be.
+    WithdrawNfts {
+        token_ids: Vec<TokenKey>,
+    },
+    /// Returns a token right away, skipping its unbonding period, at the cost of
+    /// `State.emergency_penalty` percent of its pending rewards. Refused while the token is
+    /// locked, since rewards claimed at the lock multiplier are out of the penalty's reach.
+    EmergencyWithdraw {
+        nft_address: String,
+        token_id: String,
+    },
+    /// Stakes an unstaking token again without moving it. It earns again from this block on,
+    /// with the weight it had before, less its lock multiplier if the lock is over.
+    CancelUnstake {
+        nft_address: String,
+        token_id: String,
+    },
+    GetReward {
+        token_ids: Vec<TokenKey>,
+    },
+    Receive(Cw20ReceiveMsg),
+    /// Timelocked, see `SetTimelockDelay`.
+    SetRewardWallet {
+        address: String,
+    },
+    /// Offers ownership to `address`, replacing any earlier offer. Nothing changes until that
+    /// address accepts it.
+    ProposeOwner {
+        address: String,
+        expires_at: Option<u64>,
+    },
+    AcceptOwnership {},
+    /// Lets `address` send the messages of `role`. Only the owner may grant or revoke roles.
+    GrantRole {
+        role: Role,
+        address: String,
+    },
+    RevokeRole {
+        role: Role,
+        address: String,
+    },
+    /// Withdraws the pending offer. Only the current owner may send it.
+    CancelOwnershipTransfer {},
+    /// Unbonding period of the collections that do not set their own. Timelocked, see
+    /// `SetTimelockDelay`.
+    SetStakingPeriod {
+        time: u64,
+    },
+    /// Timelocked, see `SetTimelockDelay`.
+    SetTokenAddress {
+        address: String,
+    },
+    /// Queues the timelocked messages for `delay` seconds before they can be executed, which
+    /// changing the delay itself is subject to as well. At most 30 days, so a queued change can
+    /// always be executed.
+    SetTimelockDelay {
+        delay: u64,
+    },
+    /// Applies a queued action once its delay has passed. Only senders allowed to queue the
+    /// action may execute or cancel it.
+    ExecuteQueuedAction {
+        id: u64,
+    },
+    CancelQueuedAction {
+        id: u64,
+    },
+    /// Same as `SetPaused` on `PauseSwitch::Staking`, with `flag` telling whether staking is
+    /// allowed.
+    SetStake {
+        flag: bool,
+    },
+    SetPaused {
+        switch: PauseSwitch,
+        paused: bool,
+    },
+    SetDistributePeriod {
+        time: u64,
+    },
+    Migrate {
+        amount: Uint128,
+        address: String,
+        id: Vec<TokenKey>,
+    },
+    SetClaimAmount {
+        amount: Uint128,
+    },
+    AddNftAddress {
+        address: String,
+    },
+    /// Drops a collection from the allowed list. Refused while any of its tokens are still held,
+    /// staked or unbonding.
+    RemoveNftAddress {
+        address: String,
+    },
+    /// Sets the share of pending rewards forfeited by `EmergencyWithdraw` and who receives it.
+    /// Timelocked, see `SetTimelockDelay`, since it applies to the tokens already staked.
+    SetEmergencyPenalty {
+        percent: u64,
+        treasury: Option<String>,
+    },
+    /// Reward multiplier of tokens staked with a lock of `duration` seconds, 100 being 1x and
+    /// 10000 the most. `None` removes the tier. Applies at once, as tokens already staked keep
+    /// the lock and weight they were staked with.
+    SetLockTier {
+        duration: u64,
+        multiplier: Option<u64>,
+    },
+    /// Stops a collection from taking new stakes while its stakers can still unstake and
+    /// withdraw. `SetCollectionConfig` enables it again.
+    DisableNftAddress {
+        address: String,
+    },
+    SetRewardEmission {
+        rate: Uint128,
+        start_time: u64,
+        end_time: u64,
+    },
+    /// Adds a reward stream funded by `funder`. Owner only, and capped since every stake,
+    /// unstake and claim settles each stream.
+    AddRewardStream {
+        asset: RewardAsset,
+        funder: String,
+    },
+    /// Shares the attached `State.denom` coins through a native reward stream. Only the stream's
+    /// funder may send it.
+    DistributeNativeReward {
+        stream_id: u64,
+    },
+    /// Weight of `nft_address` tokens carrying the trait, where an unweighted token has 100 and
+    /// 10000 is the most. A token with several weighted traits takes the highest. `None` clears
+    /// the weight. Tokens already staked keep the weight they were staked with.
+    SetTraitWeight {
+        nft_address: String,
+        trait_type: String,
+        value: String,
+        weight: Option<u64>,
+    },
+    /// Same as `SetTraitWeight` for a single token, overriding its traits.
+    SetTokenWeight {
+        nft_address: String,
+        token_id: String,
+        weight: Option<u64>,
+    },
+    /// Replaces the staking parameters of a registered collection. `reward_weight` is bounded
+    /// like `SetTraitWeight`. Tokens already staked keep the weight they were staked with.
+    SetCollectionConfig {
+        address: String,
+        reward_weight: u64,
+        enabled: bool,
+        max_staked: Option<u64>,
+    },
+    /// Unbonding period of a registered collection, `None` falling back to `SetStakingPeriod`.
+    /// Timelocked, see `SetTimelockDelay`.
+    SetUnbondingPeriod {
+        address: String,
+        unbonding_period: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseSwitch {
+    /// Covers `CancelUnstake` too, which stakes a token again.
+    Staking,
+    Unstaking,
+    /// Covers `EmergencyWithdraw` too.
+    Withdrawing,
+    Claiming,
+    /// Covers funding the emission too.
+    Distributing,
+}
+
+/// Payload of the `Cw721ExecuteMsg::SendNft` that stakes a token. An empty payload, or anything
+/// else that is not a JSON object, stakes without a lock as it did before lock tiers existed. An
+/// object that does not parse as a `StakeMsg` is refused with `InvalidLockTier`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct StakeMsg {
+    /// One of the durations set through `SetLockTier`. The token earns with the tier's multiplier
+    /// and cannot be unstaked until the lock ends, then goes back to its weight without a lock.
+    pub lock_duration: Option<u64>,
+}
+
+/// Payloads accepted through `Cw20ExecuteMsg::Send` from `State.token_address`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    /// Shares the sent amount between all staked tokens. Only the reward wallet may send it.
+    DistributeReward {},
+    /// Tops up the balance streamed by `SetRewardEmission`. Only the reward wallet may send it.
+    FundEmission {},
+    /// Shares the sent amount through an additional reward stream. Only the stream's funder may
+    /// send it, and only in the stream's token.
+    DistributeStreamReward { stream_id: u64 },
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-  GetStateInfo{},
-  GetCurrentTime{},
-  GetToken{token_id:String},
-  GetMyIds{address:String},
-  GetMyInfo{address:String},
+    GetStateInfo {},
+    GetCurrentTime {},
+    GetToken {
+        nft_address: String,
+        token_id: String,
+    },
+    GetMyIds {
+        address: String,
+    },
+    GetMyInfo {
+        address: String,
+    },
+    GetRewardStreams {},
+    GetTokenRewards {
+        token_ids: Vec<TokenKey>,
+    },
+    /// What `GetReward` on every token of `owner` would transfer at the current block.
+    PendingRewards {
+        owner: String,
+    },
+    /// What `GetReward`, or `WithdrawNft` once unbonded, would transfer for `token_ids` at the
+    /// current block.
+    SimulateClaim {
+        token_ids: Vec<TokenKey>,
+    },
+    /// Funded rewards waiting to be rolled into the next distribution of each stream.
+    GetRewardRemainders {},
+    GetTraitWeights {
+        nft_address: String,
+    },
+    GetCollections {},
+    GetPendingOwner {},
+    GetQueuedActions {},
+    GetPauseState {},
+    Stats {},
+    /// Distributions of `stream_id`, the primary stream by default, oldest first. The emission
+    /// is paid out every second rather than in distributions, so it is not listed.
+    Distributions {
+        stream_id: Option<u64>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Activity of `address`, oldest first.
+    UserHistory {
+        address: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Addresses granted `role`, not counting the owner who holds every role.
+    GetRoleHolders {
+        role: Role,
+    },
+    GetLockTiers {},
+    /// Staked token keys in `(nft_address, token_id)` order.
+    AllTokens {
+        start_after: Option<TokenKey>,
+        limit: Option<u32>,
+    },
+    /// Staked tokens in `(nft_address, token_id)` order, optionally only those with `status`.
+    AllTokenInfos {
+        start_after: Option<TokenKey>,
+        limit: Option<u32>,
+        status: Option<String>,
+    },
+    /// Staked tokens of `owner` in `(nft_address, token_id)` order.
+    TokensByOwner {
+        owner: String,
+        start_after: Option<TokenKey>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenRewardsResponse {
+    pub nft_address: String,
+    pub token_id: String,
+    /// Pending amount per stream, the primary stream first as id 0.
+    pub rewards: Vec<StreamRewardResponse>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ClaimSimulationResponse {
+    /// Amount per stream sent to the owner, the primary stream first as id 0.
+    pub total: Vec<StreamRewardResponse>,
+    pub tokens: Vec<TokenRewardsResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StatsResponse {
+    pub total_staked: Uint128,
+    pub collections: Vec<CollectionStatsResponse>,
+    pub unstaking_count: u64,
+    pub last_distribution_amount: Uint128,
+    /// `None` before the first `DistributeReward`.
+    pub last_distribution_time: Option<u64>,
+    /// Primary stream rewards handed to stakers so far, from `DistributeReward` and from the
+    /// emission.
+    pub total_distributed: Uint128,
+    /// Primary stream rewards paid out of `total_distributed`, forfeited penalties excluded.
+    pub total_claimed: Uint128,
+    /// Yearly primary stream reward of a token with the default weight, extrapolated from the
+    /// recent distributions plus what the emission still pays within the year. `None` while
+    /// nothing is staked, or with neither two distributions nor an active emission.
+    pub annual_reward_per_nft: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CollectionStatsResponse {
+    pub address: String,
+    pub total_staked: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PauseStateResponse {
+    pub staking: bool,
+    #[serde(flatten)]
+    pub pause_state: PauseState,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TraitWeightResponse {
+    pub trait_type: String,
+    pub value: String,
+    pub weight: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LockTierResponse {
+    pub duration: u64,
+    pub multiplier: u64,
+}
+
+/// An amount of one reward stream, the primary stream being id 0.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StreamRewardResponse {
+    pub stream_id: u64,
+    pub asset: RewardAsset,
+    pub amount: Uint128,
+}
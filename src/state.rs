@@ -1,45 +1,358 @@
-use cosmwasm_std::{ Uint128};
+use cosmwasm_std::Uint128;
 
-use cw_storage_plus::{Item, Map};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-
 pub const CONFIG: Item<State> = Item::new("config_state");
-pub const TOKENINFO : Map<&str,TokenInfo> = Map::new("config_nfts");
-pub const OWNEDTOKEN : Map<&str, Vec<String>> = Map::new("config_owned");
-pub const CLAIMFLAG : Map<&str,bool> = Map::new("claim_config");
+/// Ownership offered through `ProposeOwner` and not accepted yet.
+pub const PENDING_OWNER: Item<PendingOwner> = Item::new("pending_owner");
+/// Admin actions waiting for `State.timelock_delay` to pass, keyed by id.
+pub const QUEUED_ACTIONS: Map<u64, QueuedAction> = Map::new("queued_actions");
+pub const QUEUED_ACTION_COUNT: Item<u64> = Item::new("queued_action_count");
+/// Switches pausing single operations. Staking has its own in `State.can_stake`.
+pub const PAUSE_STATE: Item<PauseState> = Item::new("pause_state");
+/// Every distribution keyed by `(stream_id, id)`, ids counting up across all streams.
+pub const DISTRIBUTIONS: Map<(u64, u64), Distribution> = Map::new("distributions");
+pub const DISTRIBUTION_COUNT: Item<u64> = Item::new("distribution_count");
+/// Append-only activity log keyed by `(address, id)`, ids counting up per address.
+pub const USER_HISTORY: Map<(&str, u64), Activity> = Map::new("user_history");
+pub const USER_HISTORY_COUNT: Map<&str, u64> = Map::new("user_history_count");
+/// Running totals behind the `Stats` query.
+pub const STATS: Item<Stats> = Item::new("stats");
+/// Role holders keyed by `(role, address)`. The owner holds every role without an entry.
+pub const ROLES: Map<(&str, &str), bool> = Map::new("roles");
+pub const CLAIMFLAG: Map<&str, bool> = Map::new("claim_config");
+/// Reward multiplier of each lock duration in seconds that stakers can pick, 100 being 1x.
+pub const LOCK_TIERS: Map<u64, u64> = Map::new("lock_tiers");
+/// Staked tokens still at a locked weight, keyed by `(lock_end, nft_address, token_id)` so the
+/// locks that ran out come first.
+pub const LOCK_ENDS: Map<(u64, &str, &str), bool> = Map::new("lock_ends");
+/// Staking parameters of every collection in `State.nft_address`.
+pub const COLLECTIONS: Map<&str, CollectionInfo> = Map::new("collections");
+/// Reward streams next to the primary `State.token_address` one, which takes id 0.
+pub const REWARD_STREAMS: Map<u64, RewardStream> = Map::new("reward_streams");
+pub const STREAM_COUNT: Item<u64> = Item::new("reward_stream_count");
+/// Progress of a token in an additional stream, keyed by `(stream_id, nft_address, token_id)`.
+/// A missing entry means the token has not been settled against that stream yet.
+pub const STREAM_REWARDS: Map<(u64, &str, &str), StreamReward> = Map::new("stream_rewards");
+/// Owner-set weights keyed by `(nft_address, trait_type, value)`.
+pub const TRAIT_WEIGHTS: Map<(&str, &str, &str), u64> = Map::new("trait_weights");
+/// Owner-set weights keyed by `(nft_address, token_id)`. They take precedence over trait weights.
+pub const TOKEN_WEIGHTS: Map<(&str, &str), u64> = Map::new("token_weights");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
-    pub owner:String,
-    pub denom:String,
-    pub staking_period : u64,
-    pub reward_wallet : String,
-    pub total_staked : Uint128,
-    pub nft_address : Vec<String>,
-    pub token_address : String,
-    pub can_stake : bool,
-    pub last_distribute:u64,
-    pub distribute_period:u64,
-    pub claim_reward:Uint128
+    pub owner: String,
+    pub denom: String,
+    pub staking_period: u64,
+    pub reward_wallet: String,
+    pub total_staked: Uint128,
+    pub nft_address: Vec<String>,
+    pub token_address: String,
+    pub can_stake: bool,
+    pub last_distribute: u64,
+    pub distribute_period: u64,
+    pub claim_reward: Uint128,
+    /// Sum of every share per unit of weight handed out by `DistributeReward` since
+    /// instantiation, scaled up by `REWARD_SCALE` so shares smaller than one token still count.
+    pub reward_per_token: Uint128,
+    /// Reward tokens streamed to stakers per second between `emission_start` and `emission_end`.
+    pub emission_rate: Uint128,
+    pub emission_start: u64,
+    pub emission_end: u64,
+    /// Funded through `FundEmission` and not yet streamed into `reward_per_token`.
+    pub emission_balance: Uint128,
+    pub last_emission: u64,
+    /// Part of past distributions that did not divide evenly between the staked tokens, scaled
+    /// like `reward_per_token`. It is added to the next distribution instead of being dropped.
+    pub reward_remainder: Uint128,
+    /// Sum of the weights of all staked tokens, which distributions are split over.
+    pub total_weight: Uint128,
+    /// Percentage of the pending rewards forfeited by `EmergencyWithdraw`.
+    pub emergency_penalty: u64,
+    /// Receives forfeited rewards. They are shared between the remaining stakers when unset.
+    pub treasury: Option<String>,
+    /// Seconds a `TimelockedAction` waits in the queue. Actions apply at once while it is 0.
+    pub timelock_delay: u64,
 }
 
-
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct TokenInfo {
-    pub owner : String,
+    pub owner: String,
+    pub token_id: String,
+    pub status: String,
+    pub unstake_time: u64,
+    pub stake_time: u64,
+    pub reward: Uint128,
+    /// Value of `State.reward_per_token` when `reward` was last brought up to date.
+    pub reward_checkpoint: Uint128,
+    pub nft_address: String,
+    /// Share of distributions relative to the other staked tokens, fixed when the token is staked
+    /// and only lowered to `base_weight` once its lock is over.
+    pub weight: u64,
+    /// `weight` without the lock tier multiplier.
+    pub base_weight: u64,
+    /// The token cannot be unstaked before this time. 0 when it was staked without a lock.
+    pub lock_end: u64,
+}
+
+pub struct TokenIndexes<'a> {
+    pub owner: MultiIndex<'a, String, TokenInfo, (String, String)>,
+    pub status: MultiIndex<'a, String, TokenInfo, (String, String)>,
+}
+
+impl<'a> IndexList<TokenInfo> for TokenIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<TokenInfo>> + '_> {
+        let v: Vec<&dyn Index<TokenInfo>> = vec![&self.owner, &self.status];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Staked tokens keyed by `(nft_address, token_id)`, so equal ids from different collections
+/// never collide, and indexed by owner and status.
+pub fn tokens<'a>() -> IndexedMap<'a, (&'a str, &'a str), TokenInfo, TokenIndexes<'a>> {
+    let indexes = TokenIndexes {
+        owner: MultiIndex::new(
+            |token_info: &TokenInfo| token_info.owner.clone(),
+            "staked_nfts",
+            "staked_nfts__owner",
+        ),
+        status: MultiIndex::new(
+            |token_info: &TokenInfo| token_info.status.clone(),
+            "staked_nfts",
+            "staked_nfts__status",
+        ),
+    };
+    IndexedMap::new("staked_nfts", indexes)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct Stats {
+    /// Tokens unstaked and not withdrawn yet.
+    pub unstaking_count: u64,
+    /// Sum of every `DistributeReward` amount. The emission is counted apart, in
+    /// `total_emission_funded`.
+    pub total_distributed: Uint128,
+    /// Sum of every `FundEmission` amount. What is left of it is `State.emission_balance`, so
+    /// the part already emitted is the difference.
+    pub total_emission_funded: Uint128,
+    /// Primary stream rewards paid to stakers, forfeited penalties excluded.
+    pub total_claimed: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Activity {
+    pub id: u64,
+    pub kind: ActivityKind,
+    pub time: u64,
+    pub nft_address: String,
     pub token_id: String,
-    pub status : String,
-    pub unstake_time:u64,
-    pub stake_time:u64,
-    pub reward:Uint128,
-    pub nft_address:String
+    /// Primary stream reward paid for the token, 0 for actions paying nothing.
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+    Stake,
+    Unstake,
+    CancelUnstake,
+    Withdraw,
+    EmergencyWithdraw,
+    Claim,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Distribution {
+    pub id: u64,
+    /// Stream the amount was paid in, the primary stream being id 0.
+    pub stream_id: u64,
+    pub time: u64,
+    pub amount: Uint128,
+    /// Tokens staked at the time, which the amount was shared between.
+    pub total_staked: Uint128,
+    /// Share of a token with the default weight, other tokens get `share * weight / 100`.
+    pub share: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct PauseState {
+    pub unstaking: bool,
+    pub withdrawing: bool,
+    pub claiming: bool,
+    pub distributing: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Switches staking on and off.
+    Pauser,
+    /// Runs the distribution schedule and the emission.
+    Distributor,
+    /// Manages the allowed collections and their staking parameters.
+    CollectionManager,
+    /// Manages where rewards come from and where forfeits go.
+    Treasurer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Pauser => "pauser",
+            Role::Distributor => "distributor",
+            Role::CollectionManager => "collection_manager",
+            Role::Treasurer => "treasurer",
+        }
+    }
+}
+
+/// Admin changes that alter the terms for stakers, so they are announced before applying.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelockedAction {
+    SetTokenAddress {
+        address: String,
+    },
+    SetRewardWallet {
+        address: String,
+    },
+    SetStakingPeriod {
+        time: u64,
+    },
+    SetTimelockDelay {
+        delay: u64,
+    },
+    SetUnbondingPeriod {
+        address: String,
+        unbonding_period: Option<u64>,
+    },
+    SetEmergencyPenalty {
+        percent: u64,
+        treasury: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct QueuedAction {
+    pub id: u64,
+    pub action: TimelockedAction,
+    /// Earliest time the action can be executed.
+    pub eta: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub struct Image {   
+pub struct PendingOwner {
+    pub address: String,
+    /// The offer can no longer be accepted from this time on. It never expires when unset.
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CollectionInfo {
+    pub address: String,
+    /// Seconds between unstaking and withdrawing. `State.staking_period` applies when unset.
+    pub unbonding_period: Option<u64>,
+    /// Applied on top of the rarity weight of the collection's tokens, 100 leaving it as is.
+    pub reward_weight: u64,
+    /// Disabled collections accept no new stakes, but their stakers can still leave.
+    pub enabled: bool,
+    /// Most tokens of the collection staked at the same time, unlimited when unset.
+    pub max_staked: Option<u64>,
+    pub total_staked: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RewardStream {
+    pub id: u64,
+    pub asset: RewardAsset,
+    /// The only sender allowed to distribute through this stream.
+    pub funder: String,
+    pub reward_per_token: Uint128,
+    /// Same as `State.reward_remainder`, for this stream.
+    pub reward_remainder: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RewardAsset {
+    Cw20 {
+        address: String,
+    },
+    /// Paid with `BankMsg::Send`. Only `State.denom` is accepted.
+    Native {
+        denom: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct StreamReward {
+    pub reward: Uint128,
+    pub reward_checkpoint: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenKey {
+    pub nft_address: String,
+    pub token_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Metadata {
     pub image: Option<String>,
+    pub attributes: Option<Vec<Trait>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Trait {
+    pub trait_type: String,
+    pub value: String,
+}
+
+// Storage layout of v0.10.0, only read by `migrate`. Tokens were keyed by `token_id` alone and
+// neither the state nor the tokens carried reward accumulator fields yet.
+
+pub const LEGACY_CONFIG: Item<LegacyState> = Item::new("config_state");
+pub const LEGACY_TOKENINFO: Map<&str, LegacyTokenInfo> = Map::new("config_nfts");
+pub const LEGACY_OWNEDTOKEN: Map<&str, Vec<String>> = Map::new("config_owned");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyState {
+    pub owner: String,
+    pub denom: String,
+    pub staking_period: u64,
+    pub reward_wallet: String,
+    pub total_staked: Uint128,
+    pub nft_address: Vec<String>,
+    pub token_address: String,
+    pub can_stake: bool,
+    pub last_distribute: u64,
+    pub distribute_period: u64,
+    pub claim_reward: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LegacyTokenInfo {
+    pub owner: String,
+    pub token_id: String,
+    pub status: String,
+    pub unstake_time: u64,
+    pub stake_time: u64,
+    pub reward: Uint128,
+    pub nft_address: String,
 }